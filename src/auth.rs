@@ -0,0 +1,69 @@
+use tide::{Middleware, Next, Request, utils::async_trait};
+use crate::{State, error_response};
+use crate::error::GuavaError;
+use crate::service::auth_service::{Principal, Role};
+use crate::service::user_service::SESSION_PREFIX;
+
+/// Route middleware rejecting requests without an API key or session token of at least the given role.
+///
+/// Credentials are read from `Authorization: Bearer <key>` or `X-Api-Key`. The authenticated
+/// `Principal` is stored in the request extensions for handlers.
+pub struct RequireRole {
+    role: Role,
+    allow_anonymous: bool,
+}
+
+impl RequireRole {
+    pub fn new(role: Role) -> Self {
+        RequireRole {
+            role,
+            allow_anonymous: false,
+        }
+    }
+
+    /// Also lets through requests carrying no key at all
+    pub fn allow_anonymous(mut self, allow: bool) -> Self {
+        self.allow_anonymous = allow;
+        self
+    }
+}
+
+fn request_key(req: &Request<State>) -> Option<String> {
+    if let Some(header) = req.header("Authorization") {
+        return header.as_str().strip_prefix("Bearer ").map(|key| key.trim().to_string());
+    }
+
+    req.header("X-Api-Key").map(|header| header.as_str().trim().to_string())
+}
+
+#[async_trait]
+impl Middleware<State> for RequireRole {
+    async fn handle(&self, mut req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let key = match request_key(&req) {
+            Some(key) => key,
+            None if self.allow_anonymous => return Ok(next.run(req).await),
+            None => return Ok(error_response(GuavaError::Unauthorized(String::from("api key required"))).await),
+        };
+
+        let principal = if key.starts_with(SESSION_PREFIX) {
+            req.state().user_service.authenticate(&key).await
+                .map(|user| user.map(|user| Principal { id: user.user_id, role: user.role }))
+        } else {
+            req.state().auth_service.authenticate(&key).await
+                .map(|api_key| api_key.map(|api_key| Principal { id: api_key.key_id, role: api_key.role }))
+        };
+
+        let principal = match principal {
+            Ok(Some(principal)) => principal,
+            Ok(None) => return Ok(error_response(GuavaError::Unauthorized(String::from("invalid api key or session"))).await),
+            Err(e) => return Ok(error_response(e).await),
+        };
+
+        if principal.role < self.role {
+            return Ok(error_response(GuavaError::Forbidden(String::from("missing the required role"))).await);
+        }
+
+        req.set_ext(principal);
+        Ok(next.run(req).await)
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashSet;
+use async_std::{channel, io::ReadExt, task};
+use futures::{AsyncBufRead, TryStreamExt};
+use sha2::{Digest, Sha256};
+use crate::error::GuavaError;
+use crate::service::content_service::ContentService;
+use crate::service::playlist_service::PlaylistManifest;
+
+/// Bumped whenever the archive layout changes, so old ETags no longer match
+const LAYOUT_VERSION: &str = "guava-bundle-1";
+
+const BLOCK_LEN: u64 = 512;
+
+/// Name of the manifest within a bundle
+const MANIFEST_NAME: &str = "manifest.json";
+
+/// Part of a bundle; blobs are only read from the store while streaming
+enum Segment {
+    Bytes(Vec<u8>),
+    Blob { hash: String, len: u64 },
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Segment::Bytes(bytes) => bytes.len() as u64,
+            Segment::Blob { len, .. } => *len,
+        }
+    }
+}
+
+/// Uncompressed tar archive of a playlist: its manifest followed by every file it names,
+/// each stored under its hash.
+///
+/// The layout only depends on the manifest, so the same playlist always produces the same
+/// bytes and any range of them can be streamed without building the archive.
+pub struct Bundle {
+    segments: Vec<Segment>,
+    len: u64,
+    etag: String,
+}
+
+/// Writes `value` as a NUL terminated octal field filling `field`, or in base-256 if it is too large
+fn write_number(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    if value < 1 << (3 * digits) {
+        let octal = format!("{:0width$o}", value, width = digits);
+        field[..digits].copy_from_slice(octal.as_bytes());
+        field[digits] = 0;
+    } else {
+        let bytes = value.to_be_bytes();
+        field.fill(0);
+        let start = field.len() - bytes.len();
+        field[start..].copy_from_slice(&bytes);
+        field[0] = 0x80;
+    }
+}
+
+/// Length of the ustar `name` field; longer names are given in a PAX extended header
+const NAME_LEN: usize = 100;
+
+/// ustar header of an entry; times and owners are fixed so the archive is reproducible.
+/// Names longer than the `name` field are truncated.
+fn ustar_header(name: &str, len: u64, type_flag: u8) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK_LEN as usize];
+    let name = &name.as_bytes()[..name.len().min(NAME_LEN)];
+    header[..name.len()].copy_from_slice(name);
+    write_number(&mut header[100..108], 0o644);
+    write_number(&mut header[108..116], 0);
+    write_number(&mut header[116..124], 0);
+    write_number(&mut header[124..136], len);
+    write_number(&mut header[136..148], 0);
+    header[156] = type_flag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // the checksum is calculated with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..155].copy_from_slice(format!("{:06o}\0", checksum).as_bytes());
+
+    header
+}
+
+/// PAX record giving the full path of the next entry, "<length> path=<name>\n" where the
+/// length counts the whole record including its own digits
+fn pax_path_record(name: &str) -> Vec<u8> {
+    let body_len = " path=\n".len() + name.len();
+    let mut len = body_len + 1;
+    while len != body_len + len.to_string().len() {
+        len = body_len + len.to_string().len();
+    }
+    format!("{} path={}\n", len, name).into_bytes()
+}
+
+/// Headers of a regular file, preceded by a PAX extended header if its name is too long
+fn tar_header(name: &str, len: u64) -> Vec<u8> {
+    if name.len() <= NAME_LEN {
+        return ustar_header(name, len, b'0');
+    }
+
+    let record = pax_path_record(name);
+    let mut header = ustar_header("PaxHeader", record.len() as u64, b'x');
+    header.extend_from_slice(&record);
+    header.extend(padding(record.len() as u64));
+    header.extend(ustar_header(name, len, b'0'));
+    header
+}
+
+/// Zeroes padding a file of `len` bytes to a whole number of blocks
+fn padding(len: u64) -> Vec<u8> {
+    vec![0u8; ((BLOCK_LEN - len % BLOCK_LEN) % BLOCK_LEN) as usize]
+}
+
+impl Bundle {
+    pub fn new(manifest: &PlaylistManifest) -> Result<Bundle, GuavaError> {
+        let manifest_json = serde_json::to_vec_pretty(manifest)
+            .map_err(|e| GuavaError::Internal(format!("failed to serialise manifest: {}", e)))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(LAYOUT_VERSION.as_bytes());
+        hasher.update(&manifest_json);
+        let etag = format!("\"{}\"", hex::encode(hasher.finalize()));
+
+        let manifest_len = manifest_json.len() as u64;
+        let mut segments = vec![
+            Segment::Bytes(tar_header(MANIFEST_NAME, manifest_len)),
+            Segment::Bytes(manifest_json),
+            Segment::Bytes(padding(manifest_len)),
+        ];
+
+        let mut added = HashSet::new();
+        for entry in &manifest.content {
+            // the same file may be in a playlist more than once
+            if !added.insert(entry.hash.as_str()) {
+                continue;
+            }
+
+            segments.push(Segment::Bytes(tar_header(&entry.hash, entry.size)));
+            segments.push(Segment::Blob { hash: entry.hash.clone(), len: entry.size });
+            segments.push(Segment::Bytes(padding(entry.size)));
+        }
+        // end of archive
+        segments.push(Segment::Bytes(vec![0u8; 2 * BLOCK_LEN as usize]));
+
+        let len = segments.iter().map(Segment::len).sum();
+        Ok(Bundle { segments, len, etag })
+    }
+
+    /// Length of the archive in bytes
+    pub fn size(&self) -> u64 {
+        self.len
+    }
+
+    /// Strong ETag, derived from the manifest the archive is built from
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// Streams `len` bytes of the archive starting at `start`, reading blobs as they are reached
+    pub fn stream(self, content_service: ContentService, start: u64, len: u64) -> impl AsyncBufRead + Unpin + Send + Sync + 'static {
+        // a small bounded queue keeps memory use flat and stops reading when the client does
+        let (sender, receiver) = channel::bounded::<std::io::Result<Vec<u8>>>(4);
+
+        task::spawn(async move {
+            let mut offset = 0u64;
+            let end = start + len;
+            for segment in self.segments {
+                let segment_start = offset;
+                offset += segment.len();
+                if offset <= start || segment_start >= end {
+                    continue;
+                }
+
+                let from = start.saturating_sub(segment_start);
+                let to = (end - segment_start).min(segment.len());
+                let result = match segment {
+                    Segment::Bytes(bytes) => sender.send(Ok(bytes[from as usize..to as usize].to_vec())).await.map_err(|_| ()),
+                    Segment::Blob { hash, .. } => send_blob(&content_service, &sender, &hash, from, to - from).await,
+                };
+                if result.is_err() {
+                    return;
+                }
+            }
+        });
+
+        receiver.into_async_read()
+    }
+}
+
+/// Sends part of a blob in chunks, failing once the receiver is gone or the blob can't be read
+async fn send_blob(content_service: &ContentService, sender: &channel::Sender<std::io::Result<Vec<u8>>>, hash: &str, start: u64, len: u64) -> Result<(), ()> {
+    let read = async {
+        content_service.check_blob_before_read(hash).await?;
+        content_service.store().range(hash, start, len).await
+    }.await;
+
+    let mut reader = match read {
+        Ok(reader) => reader,
+        Err(e) => {
+            tide::log::error!("Failed to read {} for bundle: {}", hash, e);
+            sender.send(Err(std::io::Error::other(e.to_string()))).await.ok();
+            return Err(());
+        }
+    };
+
+    let mut remaining = len;
+    while remaining > 0 {
+        let mut chunk = vec![0u8; remaining.min(64 * 1024) as usize];
+        let read = match reader.read(&mut chunk).await {
+            Ok(0) => Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("{} is shorter than expected", hash))),
+            Ok(read) => Ok(read),
+            Err(e) => Err(e),
+        };
+
+        match read {
+            Ok(read) => {
+                chunk.truncate(read);
+                remaining -= read as u64;
+                sender.send(Ok(chunk)).await.map_err(|_| ())?;
+            },
+            Err(e) => {
+                tide::log::error!("Failed to read {} for bundle: {}", hash, e);
+                sender.send(Err(e)).await.ok();
+                return Err(());
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::TryInto, sync::Arc};
+    use async_std::{io::ReadExt, task};
+    use mongodb::{Client, options::ClientOptions};
+    use crate::media::MediaInfo;
+    use crate::service::content_service::{ContentService, GuavaContentType};
+    use crate::service::playlist_service::{ManifestEntry, PlaylistManifest};
+    use crate::storage::local::LocalBlobStore;
+    use super::*;
+
+    fn entry(hash: &str, size: u64) -> ManifestEntry {
+        ManifestEntry {
+            name: format!("file {}", &hash[..4]),
+            content_id: hash[..24].to_string(),
+            content_type: GuavaContentType::Model,
+            hash: hash.to_string(),
+            size,
+            mime_type: String::from("application/octet-stream"),
+            media: MediaInfo::default(),
+            path: hash.to_string(),
+        }
+    }
+
+    fn manifest(content: Vec<ManifestEntry>) -> PlaylistManifest {
+        PlaylistManifest {
+            name: String::from("bundle"),
+            identifier: String::from("bundle"),
+            content,
+            missing: vec![],
+        }
+    }
+
+    fn read_octal(field: &[u8]) -> u64 {
+        let digits = std::str::from_utf8(field).unwrap().trim_matches(|c| c == '\0' || c == ' ');
+        u64::from_str_radix(digits, 8).unwrap()
+    }
+
+    /// Sum of a header's bytes with its checksum field counted as spaces
+    fn checksum(header: &[u8]) -> u64 {
+        header.iter().enumerate()
+            .map(|(i, &byte)| if (148..156).contains(&i) { b' ' as u64 } else { byte as u64 })
+            .sum()
+    }
+
+    #[test]
+    fn header_checksum_matches_contents() {
+        let header = tar_header(MANIFEST_NAME, 1234);
+        assert_eq!(header.len(), BLOCK_LEN as usize);
+        assert_eq!(read_octal(&header[148..155]), checksum(&header));
+        assert_eq!(header[155], b' ');
+        assert_eq!(read_octal(&header[124..136]), 1234);
+        assert_eq!(&header[..MANIFEST_NAME.len()], MANIFEST_NAME.as_bytes());
+    }
+
+    #[test]
+    fn large_sizes_use_base_256() {
+        let len = 1 << 40;
+        let header = tar_header(MANIFEST_NAME, len);
+        assert_eq!(header[124], 0x80);
+        assert_eq!(u64::from_be_bytes(header[128..136].try_into().unwrap()), len);
+        assert_eq!(read_octal(&header[148..155]), checksum(&header));
+    }
+
+    #[test]
+    fn long_names_are_given_in_a_pax_header() {
+        let name = "a".repeat(128);
+        let headers = tar_header(&name, 10);
+        assert_eq!(headers.len(), 3 * BLOCK_LEN as usize);
+
+        let (pax, rest) = headers.split_at(BLOCK_LEN as usize);
+        let (record, file) = rest.split_at(BLOCK_LEN as usize);
+        assert_eq!(pax[156], b'x');
+        assert_eq!(read_octal(&pax[148..155]), checksum(pax));
+
+        let expected = format!("138 path={}\n", name);
+        assert_eq!(read_octal(&pax[124..136]), expected.len() as u64);
+        assert_eq!(&record[..expected.len()], expected.as_bytes());
+        assert!(record[expected.len()..].iter().all(|&byte| byte == 0));
+
+        assert_eq!(file[156], b'0');
+        assert_eq!(read_octal(&file[148..155]), checksum(file));
+    }
+
+    #[test]
+    fn pax_record_length_counts_its_own_digits() {
+        // 8 bytes of body and name push the length from one digit to two
+        assert_eq!(pax_path_record("a"), b"9 path=a\n".to_vec());
+        assert_eq!(pax_path_record("ab"), b"11 path=ab\n".to_vec());
+    }
+
+    #[test]
+    fn layout_is_fixed_by_the_manifest() {
+        let (first, second) = ("a".repeat(64), "b".repeat(64));
+        let manifest = manifest(vec![entry(&first, 10), entry(&second, 600), entry(&first, 10)]);
+        let bundle = Bundle::new(&manifest).unwrap();
+        let manifest_len = serde_json::to_vec_pretty(&manifest).unwrap().len() as u64;
+        let round_up = |len: u64| len.div_ceil(BLOCK_LEN) * BLOCK_LEN;
+
+        // duplicate entries are only stored once
+        let lens: Vec<u64> = bundle.segments.iter().map(Segment::len).collect();
+        assert_eq!(lens.len(), 3 + 2 * 3 + 1);
+        assert_eq!(bundle.size(), BLOCK_LEN + round_up(manifest_len) + BLOCK_LEN + round_up(10) + BLOCK_LEN + round_up(600) + 2 * BLOCK_LEN);
+        assert_eq!(bundle.size(), lens.iter().sum::<u64>());
+        assert_eq!(bundle.size() % BLOCK_LEN, 0);
+
+        // every header starts on a block boundary
+        let offsets: Vec<u64> = lens.iter().scan(0, |offset, len| { let start = *offset; *offset += len; Some(start) }).collect();
+        for (i, hash) in [(3, &first), (6, &second)] {
+            assert_eq!(offsets[i] % BLOCK_LEN, 0);
+            match &bundle.segments[i] {
+                Segment::Bytes(header) => assert_eq!(&header[..hash.len()], hash.as_bytes()),
+                Segment::Blob { .. } => panic!("expected a header at segment {}", i),
+            }
+        }
+
+        let again = Bundle::new(&manifest).unwrap();
+        assert_eq!(again.size(), bundle.size());
+        assert_eq!(again.etag(), bundle.etag());
+    }
+
+    async fn content_service(root: &std::path::Path) -> ContentService {
+        // the database is never reached, as blobs are not verified on read
+        let options = ClientOptions::parse("mongodb://localhost:27017").await.unwrap();
+        let db = Client::with_options(options).unwrap().database("guava-test");
+        ContentService::new(db, Arc::new(LocalBlobStore::new(root.to_path_buf())), false)
+    }
+
+    async fn read_range(manifest: &PlaylistManifest, content_service: &ContentService, start: u64, len: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        Bundle::new(manifest).unwrap().stream(content_service.clone(), start, len).read_to_end(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[test]
+    fn partial_streams_match_the_full_archive() {
+        task::block_on(async {
+            let root = std::env::temp_dir().join(format!("guava-bundle-test-{}", std::process::id()));
+            let content_service = content_service(&root).await;
+
+            let blobs = [("c".repeat(64), vec![1u8; 700]), ("d".repeat(64), vec![2u8; 100 * 1024])];
+            for (hash, data) in &blobs {
+                let source = root.join(format!("{}.upload", hash));
+                std::fs::create_dir_all(&root).unwrap();
+                std::fs::write(&source, data).unwrap();
+                content_service.store().put(hash, source.as_path().into()).await.unwrap();
+            }
+
+            let manifest = manifest(blobs.iter().map(|(hash, data)| entry(hash, data.len() as u64)).collect());
+            let size = Bundle::new(&manifest).unwrap().size();
+            let full = read_range(&manifest, &content_service, 0, size).await;
+            assert_eq!(full.len() as u64, size);
+
+            // the first blob follows the manifest and its header
+            let first_blob = match &Bundle::new(&manifest).unwrap().segments[..4] {
+                [header, json, padding, _] => header.len() + json.len() + padding.len() + BLOCK_LEN,
+                _ => unreachable!(),
+            };
+            assert_eq!(&full[first_blob as usize..first_blob as usize + 700], &blobs[0].1[..]);
+
+            // ranges starting and ending within headers, padding and blobs
+            let ranges = [(0, 1), (100, 600), (first_blob - 10, 20), (first_blob + 690, 400), (first_blob + 500, 70 * 1024), (size - 1500, 1500)];
+            for (start, len) in ranges {
+                let part = read_range(&manifest, &content_service, start, len).await;
+                assert_eq!(part, &full[start as usize..(start + len) as usize], "range {}+{}", start, len);
+            }
+
+            std::fs::remove_dir_all(&root).ok();
+        });
+    }
+}
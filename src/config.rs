@@ -0,0 +1,659 @@
+use std::{env, fmt, fs, net::ToSocketAddrs, path::PathBuf, str::FromStr};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use tide::log::LevelFilter;
+use crate::service::auth_service::Role;
+
+/// Config file read when none is given on the command line or in `GUAVA_CONFIG`
+const DEFAULT_CONFIG_PATH: &str = "guava.toml";
+
+/// Command line arguments; flags override the config file and environment
+#[derive(Parser, Debug)]
+#[command(name = "guava-server", about = "Content provider server for Guava clients")]
+pub struct Cli {
+    /// Path to a TOML config file
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, e.g. 127.0.0.1:8080
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// Log level (off, error, warn, info, debug, trace)
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// MongoDB connection string, including credentials and TLS options
+    #[arg(long)]
+    pub mongo_uri: Option<String>,
+    /// MongoDB database name
+    #[arg(long)]
+    pub database: Option<String>,
+    /// Blob storage backend (local or s3)
+    #[arg(long)]
+    pub storage_backend: Option<StorageBackend>,
+    /// Directory blobs are stored in by the local backend
+    #[arg(long)]
+    pub storage_root: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Move blobs from the old flat content directory into the sharded layout
+    MigrateLayout,
+    /// Create an API key, e.g. the first admin key
+    MintKey {
+        /// Name to remember the key by
+        #[arg(long)]
+        name: String,
+        /// Role of the key (reader, curator or admin)
+        #[arg(long, default_value = "admin")]
+        role: Role,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Local,
+    S3,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "local" => Ok(StorageBackend::Local),
+            "s3" => Ok(StorageBackend::S3),
+            _ => Err(format!("unknown storage backend '{}', expected 'local' or 's3'", value)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: String,
+    pub log_level: String,
+    pub database: DatabaseConfig,
+    pub storage: StorageConfig,
+    pub limits: LimitsConfig,
+    pub scrub: ScrubConfig,
+    pub auth: AuthConfig,
+    pub images: ImageConfig,
+    pub audio: AudioConfig,
+    pub video: VideoConfig,
+    pub thumbnails: ThumbnailConfig,
+    pub jobs: JobConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// Connection string; credentials and TLS options (`tls=true`, `tlsCAFile=...`) go here
+    pub uri: String,
+    pub name: String,
+    /// Overrides the credentials in `uri`, so they can be kept out of the config file
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// Directory blobs are stored in by the local backend
+    pub root: PathBuf,
+    /// Directory uploads are written to before being stored under their hash
+    pub staging_dir: PathBuf,
+    /// Rehash blobs the first time they are downloaded, refusing to serve mismatches
+    pub verify_on_read: bool,
+    pub s3: S3Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_upload_bytes: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScrubConfig {
+    /// Seconds between background scrubs, 0 disables them
+    pub interval_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Allow hash lookups, downloads and playlist reads without an API key
+    pub anonymous_reads: bool,
+    /// Allow anyone to register a user account, rather than only admins
+    pub open_registration: bool,
+    /// Seconds a login session stays valid
+    pub session_ttl_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImageConfig {
+    /// Largest accepted image width in pixels
+    pub max_width: u32,
+    /// Largest accepted image height in pixels
+    pub max_height: u32,
+    /// Store JPEG and TGA uploads as PNG
+    pub convert_to_png: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Transcode sound uploads that aren't OGG Vorbis, keeping the original as a variant
+    pub transcode: bool,
+    /// Vorbis quality from -0.2 to 1.0
+    pub quality: f32,
+    /// Adjust the gain of transcoded audio to `target_loudness_lufs`
+    pub normalize: bool,
+    pub target_loudness_lufs: f64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoConfig {
+    /// Reject videos Roblox can't play instead of flagging them
+    pub reject_unplayable: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    /// Generate previews of uploaded images, sounds and VP8 videos in the background
+    pub enabled: bool,
+    /// Largest width or height of a preview in pixels
+    pub max_size: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobConfig {
+    /// Background jobs run at once by this server, 0 leaves them to other servers
+    pub workers: usize,
+    /// Times a job is attempted before it is marked failed
+    pub max_attempts: u32,
+    /// Seconds before the first retry of a failed job, doubling with every attempt
+    pub retry_backoff_secs: u64,
+    /// Seconds a running job may take before it is presumed abandoned and run again
+    pub lease_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: String::from("127.0.0.1:8080"),
+            log_level: String::from("info"),
+            database: DatabaseConfig::default(),
+            storage: StorageConfig::default(),
+            limits: LimitsConfig::default(),
+            scrub: ScrubConfig::default(),
+            auth: AuthConfig::default(),
+            images: ImageConfig::default(),
+            audio: AudioConfig::default(),
+            video: VideoConfig::default(),
+            thumbnails: ThumbnailConfig::default(),
+            jobs: JobConfig::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            uri: String::from("mongodb://127.0.0.1:27017/"),
+            name: String::from("guava"),
+            username: None,
+            password: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::Local,
+            root: PathBuf::from("content"),
+            staging_dir: env::temp_dir().join("guava-uploads"),
+            verify_on_read: false,
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: String::from("http://127.0.0.1:9000"),
+            bucket: String::from("guava"),
+            region: String::from("us-east-1"),
+            access_key: String::new(),
+            secret_key: String::new(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_upload_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        ScrubConfig {
+            interval_secs: 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            anonymous_reads: true,
+            open_registration: false,
+            session_ttl_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        ImageConfig {
+            max_width: 4096,
+            max_height: 4096,
+            convert_to_png: true,
+        }
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig {
+            transcode: true,
+            quality: 0.5,
+            normalize: false,
+            target_loudness_lufs: -14.0,
+        }
+    }
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        ThumbnailConfig {
+            enabled: true,
+            max_size: 256,
+        }
+    }
+}
+
+impl Default for JobConfig {
+    fn default() -> Self {
+        JobConfig {
+            workers: 2,
+            max_attempts: 5,
+            retry_backoff_secs: 30,
+            lease_secs: 15 * 60,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "failed to parse {}: {}", path.display(), e),
+            ConfigError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads an environment variable, failing if it is set but cannot be parsed
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value.parse().map(Some)
+            .map_err(|_| ConfigError::Invalid(format!("invalid value '{}' for {}", value, name))),
+        Err(_) => Ok(None),
+    }
+}
+
+impl Config {
+    /// Builds the configuration from defaults, the config file, environment variables and
+    /// command line flags, each layer overriding the previous one.
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let path = cli.config.clone().or_else(|| env::var("GUAVA_CONFIG").ok().map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None if PathBuf::from(DEFAULT_CONFIG_PATH).is_file() => Config::from_file(PathBuf::from(DEFAULT_CONFIG_PATH))?,
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_cli(cli);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn from_file(path: PathBuf) -> Result<Config, ConfigError> {
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) => return Err(ConfigError::Read(path, e)),
+        };
+
+        toml::from_str(&contents).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        // MONGO_HOST and MONGO_PORT predate GUAVA_MONGO_URI and are still honoured
+        let host = env::var("MONGO_HOST").ok();
+        let port = env::var("MONGO_PORT").ok();
+        if host.is_some() || port.is_some() {
+            self.database.uri = format!("mongodb://{}:{}/",
+                host.as_deref().unwrap_or("127.0.0.1"),
+                port.as_deref().unwrap_or("27017"));
+        }
+
+        macro_rules! apply {
+            ($name:expr, $field:expr) => {
+                if let Some(value) = env_var($name)? {
+                    $field = value;
+                }
+            };
+        }
+
+        apply!("GUAVA_BIND_ADDRESS", self.bind_address);
+        apply!("GUAVA_LOG_LEVEL", self.log_level);
+        apply!("GUAVA_MONGO_URI", self.database.uri);
+        apply!("GUAVA_DATABASE", self.database.name);
+        if let Some(username) = env_var("GUAVA_MONGO_USERNAME")? {
+            self.database.username = Some(username);
+        }
+        if let Some(password) = env_var("GUAVA_MONGO_PASSWORD")? {
+            self.database.password = Some(password);
+        }
+        apply!("GUAVA_STORAGE_BACKEND", self.storage.backend);
+        apply!("GUAVA_STORAGE_ROOT", self.storage.root);
+        apply!("GUAVA_STAGING_DIR", self.storage.staging_dir);
+        apply!("GUAVA_VERIFY_ON_READ", self.storage.verify_on_read);
+        apply!("GUAVA_S3_ENDPOINT", self.storage.s3.endpoint);
+        apply!("GUAVA_S3_BUCKET", self.storage.s3.bucket);
+        apply!("GUAVA_S3_REGION", self.storage.s3.region);
+        apply!("GUAVA_S3_ACCESS_KEY", self.storage.s3.access_key);
+        apply!("GUAVA_S3_SECRET_KEY", self.storage.s3.secret_key);
+        apply!("GUAVA_MAX_UPLOAD_BYTES", self.limits.max_upload_bytes);
+        apply!("GUAVA_SCRUB_INTERVAL_SECS", self.scrub.interval_secs);
+        apply!("GUAVA_ANONYMOUS_READS", self.auth.anonymous_reads);
+        apply!("GUAVA_OPEN_REGISTRATION", self.auth.open_registration);
+        apply!("GUAVA_SESSION_TTL_SECS", self.auth.session_ttl_secs);
+        apply!("GUAVA_IMAGE_MAX_WIDTH", self.images.max_width);
+        apply!("GUAVA_IMAGE_MAX_HEIGHT", self.images.max_height);
+        apply!("GUAVA_IMAGE_CONVERT_TO_PNG", self.images.convert_to_png);
+        apply!("GUAVA_AUDIO_TRANSCODE", self.audio.transcode);
+        apply!("GUAVA_AUDIO_QUALITY", self.audio.quality);
+        apply!("GUAVA_AUDIO_NORMALIZE", self.audio.normalize);
+        apply!("GUAVA_AUDIO_TARGET_LOUDNESS_LUFS", self.audio.target_loudness_lufs);
+        apply!("GUAVA_VIDEO_REJECT_UNPLAYABLE", self.video.reject_unplayable);
+        apply!("GUAVA_THUMBNAILS", self.thumbnails.enabled);
+        apply!("GUAVA_THUMBNAIL_MAX_SIZE", self.thumbnails.max_size);
+        apply!("GUAVA_JOB_WORKERS", self.jobs.workers);
+        apply!("GUAVA_JOB_MAX_ATTEMPTS", self.jobs.max_attempts);
+        apply!("GUAVA_JOB_RETRY_BACKOFF_SECS", self.jobs.retry_backoff_secs);
+        apply!("GUAVA_JOB_LEASE_SECS", self.jobs.lease_secs);
+
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(bind) = &cli.bind {
+            self.bind_address = bind.clone();
+        }
+        if let Some(log_level) = &cli.log_level {
+            self.log_level = log_level.clone();
+        }
+        if let Some(uri) = &cli.mongo_uri {
+            self.database.uri = uri.clone();
+        }
+        if let Some(database) = &cli.database {
+            self.database.name = database.clone();
+        }
+        if let Some(backend) = cli.storage_backend {
+            self.storage.backend = backend;
+        }
+        if let Some(root) = &cli.storage_root {
+            self.storage.root = root.clone();
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        if self.bind_address.to_socket_addrs().map_or(true, |mut addrs| addrs.next().is_none()) {
+            return invalid(format!("bind address '{}' is not a valid host:port", self.bind_address));
+        }
+        if self.log_level.parse::<LevelFilter>().is_err() {
+            return invalid(format!("unknown log level '{}'", self.log_level));
+        }
+        if !self.database.uri.starts_with("mongodb://") && !self.database.uri.starts_with("mongodb+srv://") {
+            return invalid(String::from("database uri must start with mongodb:// or mongodb+srv://"));
+        }
+        if self.database.name.is_empty() || self.database.name.contains(|c: char| "/\\. \"$".contains(c)) {
+            return invalid(format!("invalid database name '{}'", self.database.name));
+        }
+        if self.database.username.is_some() != self.database.password.is_some() {
+            return invalid(String::from("database username and password must be set together"));
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            let s3 = &self.storage.s3;
+            if s3.bucket.is_empty() || s3.access_key.is_empty() || s3.secret_key.is_empty() {
+                return invalid(String::from("the s3 storage backend requires a bucket, access key and secret key"));
+            }
+        }
+        if self.auth.session_ttl_secs == 0 {
+            return invalid(String::from("session lifetime must be greater than zero"));
+        }
+        if self.limits.max_upload_bytes == 0 {
+            return invalid(String::from("max upload size must be greater than zero"));
+        }
+        if self.images.max_width == 0 || self.images.max_height == 0 {
+            return invalid(String::from("image dimension limits must be greater than zero"));
+        }
+        if !(16..=2048).contains(&self.thumbnails.max_size) {
+            return invalid(String::from("thumbnail size must be between 16 and 2048 pixels"));
+        }
+        if self.jobs.max_attempts == 0 {
+            return invalid(String::from("jobs must be attempted at least once"));
+        }
+        if self.jobs.lease_secs == 0 {
+            return invalid(String::from("job lease must be greater than zero"));
+        }
+        if !(-0.2..=1.0).contains(&self.audio.quality) {
+            return invalid(String::from("audio quality must be between -0.2 and 1.0"));
+        }
+        if !(-70.0..=0.0).contains(&self.audio.target_loudness_lufs) {
+            return invalid(String::from("target loudness must be between -70 and 0 LUFS"));
+        }
+
+        Ok(())
+    }
+
+    pub fn log_level_filter(&self) -> LevelFilter {
+        self.log_level.parse().unwrap_or(LevelFilter::Info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Environment variables are shared by every test thread
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Runs `test` with environment variables set, removing them afterwards
+    fn with_env<T>(vars: &[(&str, &str)], test: impl FnOnce() -> T) -> T {
+        let _lock = ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for (name, value) in vars {
+            env::set_var(name, value);
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(test));
+        for (name, _) in vars {
+            env::remove_var(name);
+        }
+        result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+
+    fn cli(args: &[&str]) -> Cli {
+        Cli::parse_from(std::iter::once("guava-server").chain(args.iter().cloned()))
+    }
+
+    fn invalid_message(config: &Config) -> String {
+        match config.validate() {
+            Err(ConfigError::Invalid(message)) => message,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let path = env::temp_dir().join(format!("guava-config-test-{}.toml", std::process::id()));
+        fs::write(&path, r#"
+            bind_address = "127.0.0.1:9000"
+            log_level = "debug"
+
+            [database]
+            name = "from_file"
+
+            [jobs]
+            workers = 4
+        "#).unwrap();
+
+        let config = with_env(&[("GUAVA_LOG_LEVEL", "warn"), ("GUAVA_DATABASE", "from_env")], || {
+            Config::load(&cli(&["--config", path.to_str().unwrap(), "--database", "from_cli"]))
+        });
+        fs::remove_file(&path).ok();
+        let config = config.unwrap();
+
+        // the file overrides defaults, the environment the file, and flags the environment
+        assert_eq!(config.bind_address, "127.0.0.1:9000");
+        assert_eq!(config.jobs.workers, 4);
+        assert_eq!(config.log_level, "warn");
+        assert_eq!(config.database.name, "from_cli");
+        // untouched by every layer
+        assert_eq!(config.jobs.max_attempts, JobConfig::default().max_attempts);
+    }
+
+    #[test]
+    fn unreadable_or_unknown_config_files_fail() {
+        let path = env::temp_dir().join(format!("guava-config-test-{}-unknown.toml", std::process::id()));
+        fs::write(&path, "unknown_field = true\n").unwrap();
+        let parsed = Config::load(&cli(&["--config", path.to_str().unwrap()]));
+        fs::remove_file(&path).ok();
+        assert!(matches!(parsed, Err(ConfigError::Parse(..))));
+
+        let missing = env::temp_dir().join("guava-config-test-missing.toml");
+        assert!(matches!(Config::load(&cli(&["--config", missing.to_str().unwrap()])), Err(ConfigError::Read(..))));
+    }
+
+    #[test]
+    fn legacy_mongo_variables_build_the_uri() {
+        let mut config = Config::default();
+        with_env(&[("MONGO_HOST", "db.internal")], || config.apply_env()).unwrap();
+        assert_eq!(config.database.uri, "mongodb://db.internal:27017/");
+
+        // GUAVA_MONGO_URI takes precedence
+        let mut config = Config::default();
+        with_env(&[("MONGO_PORT", "27018"), ("GUAVA_MONGO_URI", "mongodb://primary/")], || config.apply_env()).unwrap();
+        assert_eq!(config.database.uri, "mongodb://primary/");
+    }
+
+    #[test]
+    fn env_values_are_parsed() {
+        let mut config = Config::default();
+        with_env(&[("GUAVA_JOB_WORKERS", "8"), ("GUAVA_ANONYMOUS_READS", "false"), ("GUAVA_STORAGE_BACKEND", "s3"), ("GUAVA_MONGO_USERNAME", "guava")], || config.apply_env()).unwrap();
+        assert_eq!(config.jobs.workers, 8);
+        assert!(!config.auth.anonymous_reads);
+        assert_eq!(config.storage.backend, StorageBackend::S3);
+        assert_eq!(config.database.username.as_deref(), Some("guava"));
+
+        let mut config = Config::default();
+        let result = with_env(&[("GUAVA_JOB_WORKERS", "many")], || config.apply_env());
+        assert!(matches!(result, Err(ConfigError::Invalid(message)) if message.contains("GUAVA_JOB_WORKERS")));
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn each_invalid_setting_is_reported() {
+        type Change = fn(&mut Config);
+        let cases: Vec<(&str, Change)> = vec![
+            ("bind address", |config| config.bind_address = String::from("nowhere")),
+            ("log level", |config| config.log_level = String::from("loud")),
+            ("database uri", |config| config.database.uri = String::from("postgres://localhost")),
+            ("database name", |config| config.database.name = String::new()),
+            ("database name", |config| config.database.name = String::from("guava.test")),
+            ("username and password", |config| config.database.username = Some(String::from("guava"))),
+            ("username and password", |config| config.database.password = Some(String::from("secret"))),
+            ("s3 storage backend", |config| config.storage.backend = StorageBackend::S3),
+            ("session lifetime", |config| config.auth.session_ttl_secs = 0),
+            ("max upload size", |config| config.limits.max_upload_bytes = 0),
+            ("image dimension", |config| config.images.max_width = 0),
+            ("image dimension", |config| config.images.max_height = 0),
+            ("thumbnail size", |config| config.thumbnails.max_size = 8),
+            ("thumbnail size", |config| config.thumbnails.max_size = 4096),
+            ("attempted at least once", |config| config.jobs.max_attempts = 0),
+            ("job lease", |config| config.jobs.lease_secs = 0),
+            ("audio quality", |config| config.audio.quality = 1.5),
+            ("target loudness", |config| config.audio.target_loudness_lufs = 3.0),
+        ];
+
+        for (expected, change) in cases {
+            let mut config = Config::default();
+            change(&mut config);
+            let message = invalid_message(&config);
+            assert!(message.contains(expected), "expected '{}' in '{}'", expected, message);
+        }
+    }
+
+    #[test]
+    fn s3_backend_is_valid_with_credentials() {
+        let mut config = Config::default();
+        config.storage.backend = StorageBackend::S3;
+        config.storage.s3.bucket = String::from("guava");
+        config.storage.s3.access_key = String::from("access");
+        config.storage.s3.secret_key = String::from("secret");
+        config.validate().unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use tide::StatusCode;
+
+/// Errors surfaced by services and handlers
+#[derive(Debug)]
+pub enum GuavaError {
+    /// The requested resource does not exist
+    NotFound(String),
+    /// The resource being created already exists
+    Conflict(String),
+    /// The request could not be parsed
+    BadRequest(String),
+    /// The request was understood but its contents are invalid
+    Validation(String),
+    /// The request carries no valid credentials
+    Unauthorized(String),
+    /// The credentials do not grant access to the resource
+    Forbidden(String),
+    /// The request body exceeds a configured limit
+    TooLarge(String),
+    /// The database could not be reached or failed the operation
+    Database(mongodb::error::Error),
+    /// Reading or writing content failed
+    Io(std::io::Error),
+    /// The blob store could not be reached or failed the operation
+    Storage(String),
+    /// Any other server-side failure
+    Internal(String),
+}
+
+impl GuavaError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            GuavaError::NotFound(_) => StatusCode::NotFound,
+            GuavaError::Conflict(_) => StatusCode::Conflict,
+            GuavaError::BadRequest(_) => StatusCode::BadRequest,
+            GuavaError::Validation(_) => StatusCode::UnprocessableEntity,
+            GuavaError::Unauthorized(_) => StatusCode::Unauthorized,
+            GuavaError::Forbidden(_) => StatusCode::Forbidden,
+            GuavaError::TooLarge(_) => StatusCode::PayloadTooLarge,
+            GuavaError::Database(_) | GuavaError::Storage(_) => StatusCode::ServiceUnavailable,
+            GuavaError::Io(_) | GuavaError::Internal(_) => StatusCode::InternalServerError,
+        }
+    }
+
+    /// Machine readable error code included in error responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            GuavaError::NotFound(_) => "not_found",
+            GuavaError::Conflict(_) => "conflict",
+            GuavaError::BadRequest(_) => "bad_request",
+            GuavaError::Validation(_) => "validation_failed",
+            GuavaError::Unauthorized(_) => "unauthorized",
+            GuavaError::Forbidden(_) => "forbidden",
+            GuavaError::TooLarge(_) => "too_large",
+            GuavaError::Database(_) => "database_unavailable",
+            GuavaError::Io(_) => "io_error",
+            GuavaError::Storage(_) => "storage_unavailable",
+            GuavaError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Message safe to show to clients; server-side failure details are only logged
+    pub fn message(&self) -> String {
+        match self {
+            GuavaError::NotFound(message)
+            | GuavaError::Conflict(message)
+            | GuavaError::BadRequest(message)
+            | GuavaError::Validation(message)
+            | GuavaError::Unauthorized(message)
+            | GuavaError::Forbidden(message)
+            | GuavaError::TooLarge(message) => message.clone(),
+            GuavaError::Database(_) => String::from("database unavailable"),
+            GuavaError::Storage(_) => String::from("storage unavailable"),
+            GuavaError::Io(_) | GuavaError::Internal(_) => String::from("Internal Server Error"),
+        }
+    }
+}
+
+impl fmt::Display for GuavaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuavaError::Database(e) => write!(f, "database error: {}", e),
+            GuavaError::Io(e) => write!(f, "io error: {}", e),
+            GuavaError::Storage(message) => write!(f, "storage error: {}", message),
+            GuavaError::Internal(message) => write!(f, "internal error: {}", message),
+            _ => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for GuavaError {}
+
+impl From<mongodb::error::Error> for GuavaError {
+    fn from(e: mongodb::error::Error) -> Self {
+        GuavaError::Database(e)
+    }
+}
+
+impl From<std::io::Error> for GuavaError {
+    fn from(e: std::io::Error) -> Self {
+        GuavaError::Io(e)
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for GuavaError {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        GuavaError::Internal(e.to_string())
+    }
+}
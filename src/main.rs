@@ -1,41 +1,121 @@
+pub mod auth;
+pub mod bundle;
+pub mod config;
+pub mod error;
+pub mod media;
+pub mod range;
 pub mod service;
+pub mod storage;
 
-use std::env;
-use async_std::{path::PathBuf};
+use std::{process, sync::Arc, time::Duration};
+use async_std::{fs, io::{ReadExt, prelude::WriteExt}, path::{Path, PathBuf}, task};
+use clap::Parser;
+use sha2::{Digest, Sha256};
 use serde_json::{self, Map, Value};
 use tide::{Body, Request, Response, StatusCode, prelude::*};
-use lazy_static::lazy_static;
-use futures::{stream::TryStreamExt};
-use mongodb::{Client, Collection, bson::doc, options::{ClientOptions}};
-use crate::service::content_service::{ContentService, GuavaContentType};
-
-lazy_static! {
-    static ref MONGO_HOST: String = env::var("MONGO_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    static ref MONGO_PORT: String = env::var("MONGO_PORT").unwrap_or_else(|_| "27017".to_string());
-    static ref CONNECTION_STRING: String = format!("mongodb://{}:{}/", MONGO_HOST.as_str(), MONGO_PORT.as_str());
-}
+use mongodb::{Client, bson::oid::ObjectId, options::{ClientOptions, Credential}};
+use crate::auth::RequireRole;
+use crate::bundle::Bundle;
+use crate::config::{Cli, Command, Config, StorageBackend};
+use crate::error::GuavaError;
+use crate::range::ByteRange;
+use crate::service::auth_service::{AuthService, Principal, Role};
+use crate::media::MediaInfo;
+use crate::service::content_service::{ContentListQuery, ContentMetadata, ContentService, ContentState, GuavaContentType, StoredUpload};
+use crate::service::ingest_service::IngestService;
+use crate::service::job_service::{JobKind, JobListQuery, JobService};
+use crate::service::playlist_service::{PlaylistListQuery, PlaylistService, Visibility};
+use crate::service::scrub_service::ScrubService;
+use crate::service::thumbnail_service::{THUMBNAIL_VARIANT, ThumbnailService};
+use crate::service::user_service::UserService;
+use crate::storage::{BlobReader, BlobStore, local::LocalBlobStore, s3::S3BlobStore};
 
 #[derive(Clone)]
 struct State {
-    db: mongodb::Database,
+    config: Arc<Config>,
+    auth_service: AuthService,
+    user_service: UserService,
     content_service: ContentService,
+    playlist_service: PlaylistService,
+    scrub_service: ScrubService,
+    job_service: JobService,
+    ingest_service: IngestService,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct PlaylistContent {
-    name: String,
+#[derive(Deserialize)]
+struct UploadQuery {
+    #[serde(rename = "type")]
     content_type: GuavaContentType,
-    content_id: String,
+    name: Option<String>,
+    description: Option<String>,
+    /// Comma separated
+    tags: Option<String>,
+    author: Option<String>,
+    license: Option<String>,
+    source_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    variant: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ShareQuery {
+    share: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HashLookupRequest {
+    content_ids: Vec<String>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct GuavaPlaylist {
+#[derive(Deserialize)]
+struct RegisterRequest {
+    username: String,
+    password: String,
+    /// Only honoured for admins; everyone else registers as a curator
+    role: Option<Role>,
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct MintKeyRequest {
+    name: String,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+struct CreatePlaylistRequest {
     name: String,
-    identifier: String,
-    content: Option<Vec<PlaylistContent>>
+    identifier: Option<String>,
+    #[serde(default)]
+    visibility: Visibility,
 }
 
-async fn generate_response(status_code: StatusCode, result: Option<Value>, error: Option<String>) -> Response {
+#[derive(Deserialize)]
+struct UpdatePlaylistRequest {
+    name: Option<String>,
+    visibility: Option<Visibility>,
+}
+
+#[derive(Deserialize)]
+struct AddPlaylistContentRequest {
+    name: String,
+    content_id: String,
+}
+
+#[derive(Deserialize)]
+struct ReorderPlaylistContentRequest {
+    content_ids: Vec<String>,
+}
+
+async fn generate_response(status_code: StatusCode, result: Option<Value>, error: Option<GuavaError>) -> Response {
     let mut map = Map::new();
     map.insert(String::from("success"), Value::Bool(status_code.is_success()));
     
@@ -43,7 +123,9 @@ async fn generate_response(status_code: StatusCode, result: Option<Value>, error
     if status_code.is_success() {
         map.insert(String::from("result"), result.unwrap_or(serde_json::json!({})));
     } else {
-        map.insert(String::from("error"), Value::String(error.unwrap_or("Internal Server Error".to_string())));
+        let error = error.unwrap_or_else(|| GuavaError::Internal(String::from("no error given")));
+        map.insert(String::from("error"), Value::String(error.message()));
+        map.insert(String::from("code"), Value::String(error.code().to_string()));
     }
 
     Response::builder(status_code)
@@ -52,78 +134,1046 @@ async fn generate_response(status_code: StatusCode, result: Option<Value>, error
         .build()
 }
 
+async fn error_response(error: GuavaError) -> Response {
+    if error.status().is_server_error() {
+        tide::log::error!("{}", error);
+    }
+
+    generate_response(error.status(), None, Some(error)).await
+}
+
+async fn parse_body<T: serde::de::DeserializeOwned>(req: &mut Request<State>) -> Result<T, GuavaError> {
+    req.body_json().await.map_err(|_| GuavaError::BadRequest(String::from("invalid request body")))
+}
+
+/// Checks whether an `If-None-Match` header matches the given strong entity tag
+fn if_none_match(header: &str, etag: &str) -> bool {
+    header.split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Authenticated principal of a request to a route behind `RequireRole`
+fn principal(req: &Request<State>) -> Result<Principal, GuavaError> {
+    req.ext::<Principal>().cloned().ok_or_else(|| GuavaError::Unauthorized(String::from("api key or session required")))
+}
+
+/// Whether shared caches may keep a response: only when it was served anonymously, as
+/// reads are open to anyone. Responses to keys, sessions and share tokens stay private.
+fn publicly_cacheable(req: &Request<State>) -> bool {
+    req.state().config.auth.anonymous_reads && req.ext::<Principal>().is_none() && share_token(req).is_none()
+}
+
+/// Share token of a request, from the `share` query parameter or `X-Share-Token` header
+fn share_token(req: &Request<State>) -> Option<String> {
+    if let Some(token) = req.query::<ShareQuery>().ok().and_then(|query| query.share) {
+        return Some(token);
+    }
+
+    req.header("X-Share-Token").map(|header| header.as_str().trim().to_string())
+}
+
+/// Checks the requester may modify the playlist named by the `identifier` parameter
+async fn authorize_playlist_edit(req: &Request<State>) -> Result<(), GuavaError> {
+    let playlist = req.state().playlist_service.get(req.param("identifier").unwrap()).await?;
+    principal(req)?.ensure_can_modify(playlist.owner.as_deref())
+}
+
 /// List playlist
 /// 
-/// Lists names of all known playlists
+/// Lists a page of public playlists and the requester's own, without their content.
+/// Supports `owner` (`me` for the requester), name `prefix`, `sort` (name, created,
+/// updated), `order` (asc, desc), `limit`, and `after` set to the previous page's `next`.
 async fn list_playlist(req: Request<State>) -> tide::Result {
-    let db = &req.state().db;
-    let playlist_collection: Collection<GuavaPlaylist> = db.collection("playlist"); 
+    let playlist_service = &req.state().playlist_service;
 
-    let results = playlist_collection.find(None, None).await?;
-    let playlists = results.try_collect().await.unwrap_or_else(|_| vec![]);
-    
-    Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(playlists).unwrap()), None).await)
+    let mut query = match req.query::<PlaylistListQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(error_response(GuavaError::BadRequest(String::from("invalid query"))).await),
+    };
+    if query.owner.as_deref() == Some("me") {
+        query.owner = match principal(&req) {
+            Ok(principal) => Some(principal.id),
+            Err(e) => return Ok(error_response(e).await),
+        };
+    }
+
+    match playlist_service.list(query, req.ext::<Principal>()).await {
+        Ok(page) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(page).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn create_playlist(mut req: Request<State>) -> tide::Result {
+    let request: CreatePlaylistRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let owner = match principal(&req) {
+        Ok(principal) => principal.id,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.create(request.name, request.identifier, request.visibility, owner).await {
+        Ok(playlist) => Ok(generate_response(StatusCode::Created, Some(serde_json::value::to_value(playlist).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn get_playlist(req: Request<State>) -> tide::Result {
+    let playlist_service = &req.state().playlist_service;
+
+    let share_token = share_token(&req);
+
+    match playlist_service.get_readable(req.param("identifier").unwrap(), req.ext::<Principal>(), share_token.as_deref()).await {
+        Ok(playlist) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(playlist).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Playlist manifest
+///
+/// Resolves every item in a playlist to the information a client needs to fetch it.
+/// Private playlists need the owner, an admin or a share token.
+async fn get_playlist_manifest(req: Request<State>) -> tide::Result {
+    let playlist_service = &req.state().playlist_service;
+    let share_token = share_token(&req);
+
+    if let Err(e) = playlist_service.get_readable(req.param("identifier").unwrap(), req.ext::<Principal>(), share_token.as_deref()).await {
+        return Ok(error_response(e).await);
+    }
+
+    match playlist_service.manifest(req.param("identifier").unwrap()).await {
+        Ok(manifest) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(manifest).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Playlist bundle
+///
+/// Streams a tar archive of the playlist's manifest and every file it names, stored under
+/// their hashes. The archive is built on the fly with a layout that only depends on the
+/// manifest, so it carries a strong ETag and single `Range` requests can resume downloads.
+async fn get_playlist_bundle(req: Request<State>) -> tide::Result {
+    let playlist_service = &req.state().playlist_service;
+    let share_token = share_token(&req);
+
+    let playlist = match playlist_service.get_readable(req.param("identifier").unwrap(), req.ext::<Principal>(), share_token.as_deref()).await {
+        Ok(playlist) => playlist,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let bundle = match playlist_service.manifest(&playlist.identifier).await.and_then(|manifest| Bundle::new(&manifest)) {
+        Ok(bundle) => bundle,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let etag = bundle.etag().to_string();
+    let len = bundle.size();
+
+    let file_name: String = playlist.identifier.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "-_.".contains(c) { c } else { '_' })
+        .collect();
+    let response = Response::builder(StatusCode::Ok)
+        // playlists can change, so caches must revalidate
+        .header("Cache-Control", match playlist.visibility != Visibility::Private && publicly_cacheable(&req) {
+            true => "public, no-cache",
+            false => "private, no-cache",
+        })
+        .header("Content-Disposition", format!("attachment; filename=\"{}.tar\"", file_name))
+        .build();
+
+    let content_service = req.state().content_service.clone();
+    send_ranged(&req, response, &etag, len, "application/x-tar", |start, len| async move {
+        Ok(Box::new(bundle.stream(content_service, start, len)) as BlobReader)
+    }).await
+}
+
+/// Update playlist
+///
+/// Renames a playlist and/or changes its visibility
+async fn update_playlist(mut req: Request<State>) -> tide::Result {
+    let request: UpdatePlaylistRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.update(req.param("identifier").unwrap(), request.name, request.visibility).await {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
 }
 
+async fn delete_playlist(req: Request<State>) -> tide::Result {
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.delete(req.param("identifier").unwrap()).await {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn add_playlist_content(mut req: Request<State>) -> tide::Result {
+    let request: AddPlaylistContentRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let principal = match principal(&req) {
+        Ok(principal) => principal,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.add_content(req.param("identifier").unwrap(), request.name, request.content_id, &principal).await {
+        Ok(entry) => Ok(generate_response(StatusCode::Created, Some(serde_json::value::to_value(entry).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn remove_playlist_content(req: Request<State>) -> tide::Result {
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.remove_content(req.param("identifier").unwrap(), req.param("content_id").unwrap()).await {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn reorder_playlist_content(mut req: Request<State>) -> tide::Result {
+    let request: ReorderPlaylistContentRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.reorder_content(req.param("identifier").unwrap(), request.content_ids).await {
+        Ok(content) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(content).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Create share token
+///
+/// Mints a token granting read access to a private playlist; the token is only shown once
+async fn create_share_token(req: Request<State>) -> tide::Result {
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let created_by = match principal(&req) {
+        Ok(principal) => principal.id,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.create_share_token(req.param("identifier").unwrap(), created_by).await {
+        Ok((share_token, token)) => {
+            let mut result = serde_json::value::to_value(share_token).unwrap();
+            result["token"] = Value::String(token);
+            Ok(generate_response(StatusCode::Created, Some(result), None).await)
+        },
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn list_share_tokens(req: Request<State>) -> tide::Result {
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.list_share_tokens(req.param("identifier").unwrap()).await {
+        Ok(tokens) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(tokens).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn revoke_share_token(req: Request<State>) -> tide::Result {
+    if let Err(e) = authorize_playlist_edit(&req).await {
+        return Ok(error_response(e).await);
+    }
+    let playlist_service = &req.state().playlist_service;
+
+    match playlist_service.revoke_share_token(req.param("identifier").unwrap(), req.param("token_id").unwrap()).await {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Download asset
+///
+/// Sends the content blob, honouring single `Range` requests (guarded by `If-Range`)
+/// so interrupted downloads can be resumed; requests for several ranges get a 416. Blobs are named by hash and never change,
+/// so responses carry the hash as a strong ETag and are cacheable forever. Content only
+/// reachable through private playlists needs the same access as those playlists.
+/// Another variant of the content, such as `original`, can be chosen with `variant`.
 async fn download_asset(req: Request<State>) -> tide::Result {
+    let variant = req.query::<DownloadQuery>().ok().and_then(|query| query.variant);
+    send_content(&req, variant.as_deref()).await
+}
+
+/// Content preview
+///
+/// Sends the PNG preview of the content, once it has been generated
+async fn get_thumbnail(req: Request<State>) -> tide::Result {
+    send_content(&req, Some(THUMBNAIL_VARIANT)).await
+}
+
+/// Sends a blob of the content named by the `id` parameter, the main one unless a variant is given
+async fn send_content(req: &Request<State>, variant: Option<&str>) -> tide::Result {
     let content_service  = &req.state().content_service;
 
-    match content_service.get_hash_from_id(req.param("id").unwrap().to_string()).await {
-        Ok(hash) => {
-            match Body::from_file(PathBuf::from("content/".to_string().to_owned() + &hash.to_owned())).await {
-                Ok(body) => Ok(Response::builder(StatusCode::Ok).body(body).build()),
-                Err(_) => Ok(generate_response(StatusCode::NotFound, None::<Value>, Some(String::from("file not found"))).await)
-            }
+    let content = match content_service.get_content_from_id(req.param("id").unwrap().to_string()).await {
+        Ok(content) => content,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let share_token = share_token(req);
+    let restricted = match req.state().playlist_service.authorize_content_read(&content, req.ext::<Principal>(), share_token.as_deref()).await {
+        Ok(restricted) => restricted,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let (hash, mime_type) = match content.ensure_ready().and_then(|_| content.variant(variant)) {
+        Ok(variant) => variant,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let etag = format!("\"{}\"", hash);
+
+    let store = content_service.store();
+    if let Err(e) = content_service.check_blob_before_read(hash).await {
+        return Ok(error_response(e).await);
+    }
+
+    let len = match store.size(hash).await {
+        Ok(len) => len,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let response = Response::builder(StatusCode::Ok)
+        .header("Cache-Control", match !restricted && publicly_cacheable(req) {
+            true => "public, max-age=31536000, immutable",
+            false => "private, max-age=31536000, immutable",
+        })
+        .build();
+
+    send_ranged(req, response, &etag, len, mime_type, |start, part_len| async move {
+        match part_len == len {
+            true => store.stream(hash).await,
+            false => store.range(hash, start, part_len).await,
+        }
+    }).await
+}
+
+/// Sends a resource of `len` bytes identified by a strong ETag, completing `response`.
+///
+/// A matching `If-None-Match` gets a 304. A single `Range` is honoured with a 206 as long as
+/// `If-Range`, if given, still matches the ETag; `open` streams `len` bytes from `start`.
+async fn send_ranged<F, Fut>(req: &Request<State>, mut response: Response, etag: &str, len: u64, mime_type: &str, open: F) -> tide::Result
+where
+    F: FnOnce(u64, u64) -> Fut,
+    Fut: std::future::Future<Output = Result<BlobReader, GuavaError>>,
+{
+    response.insert_header("Accept-Ranges", "bytes");
+    response.insert_header("ETag", etag);
+
+    if req.header("If-None-Match").is_some_and(|header| if_none_match(header.as_str(), etag)) {
+        response.set_status(StatusCode::NotModified);
+        return Ok(response);
+    }
+
+    let range_header = match req.header("If-Range") {
+        Some(if_range) if if_range.as_str().trim() != etag => None,
+        _ => req.header("Range").map(|range| range.as_str()),
+    };
+
+    let (start, part_len) = match ByteRange::parse(range_header, len) {
+        ByteRange::Full => (0, len),
+        ByteRange::Partial { start, end } => {
+            response.set_status(StatusCode::PartialContent);
+            response.insert_header("Content-Range", format!("bytes {}-{}/{}", start, end, len));
+            (start, end - start + 1)
         },
-        Err(_) => Ok(generate_response(StatusCode::NotFound, None::<Value>, Some(String::from("file not found"))).await),
+        ByteRange::Unsatisfiable => {
+            response.set_status(StatusCode::RequestedRangeNotSatisfiable);
+            response.insert_header("Content-Range", format!("bytes */{}", len));
+            return Ok(response);
+        },
+    };
+
+    match open(start, part_len).await {
+        Ok(reader) => response.set_body(Body::from_reader(reader, Some(part_len as usize))),
+        Err(e) => return Ok(error_response(e).await),
     }
+
+    // set after the body, which would otherwise reset it
+    response.set_content_type(mime_type);
+    Ok(response)
 }
 
+/// Content details
+///
+/// Gets content with its metadata; content only reachable through private playlists
+/// needs the same access as those playlists
+async fn get_content(req: Request<State>) -> tide::Result {
+    let content_service = &req.state().content_service;
+
+    let content = match content_service.get_details(req.param("id").unwrap().to_string()).await {
+        Ok(content) => content,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let share_token = share_token(&req);
+    if let Err(e) = req.state().playlist_service.authorize_content_read(&content, req.ext::<Principal>(), share_token.as_deref()).await {
+        return Ok(error_response(e).await);
+    }
+
+    Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(content).unwrap()), None).await)
+}
+
+/// Update content metadata
+async fn update_content(mut req: Request<State>) -> tide::Result {
+    let metadata: ContentMetadata = match parse_body(&mut req).await {
+        Ok(metadata) => metadata,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let content_service = &req.state().content_service;
+    let content_id = req.param("id").unwrap();
+
+    let content = match content_service.get_content_from_id(content_id.to_string()).await {
+        Ok(content) => content,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    if let Err(e) = principal(&req).and_then(|principal| principal.ensure_can_modify(content.owner.as_deref())) {
+        return Ok(error_response(e).await);
+    }
+
+    match content_service.update_metadata(content_id, metadata).await {
+        Ok(content) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(content).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Content hash
+///
+/// Gets the hash of ready content, which needs the same access as downloading it; processing
+/// may still replace the hash of other content
 async fn get_hash_of_content(req: Request<State>) -> tide::Result {
     let content_service = &req.state().content_service;
-    
-    match content_service.get_hash_from_id(req.param("id").unwrap().to_string()).await {
-        Ok(hash) => Ok(generate_response(StatusCode::Ok, Some(serde_json::Value::String(String::from(hash))), None).await),
-        Err(_) => Ok(generate_response(StatusCode::NotFound, None::<Value>, Some(String::from("content not found"))).await),
+
+    let content = match content_service.get_content_from_id(req.param("id").unwrap().to_string()).await {
+        Ok(content) => content,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let share_token = share_token(&req);
+    if let Err(e) = req.state().playlist_service.authorize_content_read(&content, req.ext::<Principal>(), share_token.as_deref()).await {
+        return Ok(error_response(e).await);
+    }
+
+    match content.ensure_ready() {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, Some(serde_json::Value::String(content.hash)), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Batch hash lookup
+///
+/// Resolves the hashes, types and sizes of many pieces of content at once. Ids of unknown
+/// content are listed under `missing`, content that isn't ready yet under `not_ready`, and
+/// content only in private playlists the requester can't read under `forbidden`.
+async fn lookup_hashes(mut req: Request<State>) -> tide::Result {
+    let request: HashLookupRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let ids = match ContentService::hash_lookup_ids(request.content_ids) {
+        Ok(ids) => ids,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let share_token = share_token(&req);
+    let hidden = match req.state().playlist_service.unreadable_content_ids(&ids, req.ext::<Principal>(), share_token.as_deref()).await {
+        Ok(hidden) => hidden,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    match req.state().content_service.lookup_hashes(ids, &hidden, req.ext::<Principal>()).await {
+        Ok(lookup) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(lookup).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Streams an upload into a staging file, returning its path, SHA-256 hash and size.
+///
+/// The leading bytes are checked against the declared type before anything is written.
+async fn stage_upload(body: &mut Body, declared_type: GuavaContentType, config: &Config) -> Result<(PathBuf, String, u64), GuavaError> {
+    let max_len = config.limits.max_upload_bytes;
+    if body.len().is_some_and(|len| len as u64 > max_len) {
+        return Err(GuavaError::TooLarge(format!("uploads are limited to {} bytes", max_len)));
+    }
+
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    // read enough of the body to check it matches the declared type
+    let mut header_len = 0;
+    while header_len < GuavaContentType::SNIFF_LEN {
+        let read = body.read(&mut buffer[header_len..]).await?;
+        if read == 0 {
+            break;
+        }
+        header_len += read;
+    }
+
+    if header_len == 0 {
+        return Err(GuavaError::BadRequest(String::from("empty upload")));
+    }
+
+    let sniffed = GuavaContentType::sniff(&buffer[..header_len]);
+    if sniffed != declared_type && !(declared_type.has_unsniffable_formats() && sniffed == GuavaContentType::None) {
+        return Err(GuavaError::Validation(String::from("content does not match declared type")));
+    }
+
+    let staging_dir = Path::new(&config.storage.staging_dir);
+    fs::create_dir_all(staging_dir).await?;
+    let temp_path = staging_dir.join(format!("upload-{}", ObjectId::new().to_hex()));
+    let mut file = fs::File::create(&temp_path).await?;
+    let mut hasher = Sha256::new();
+
+    let mut chunk_len = header_len;
+    let mut total_len = 0u64;
+    while chunk_len > 0 {
+        // the declared length may be absent, so the limit is also enforced while streaming
+        total_len += chunk_len as u64;
+        if total_len > max_len {
+            fs::remove_file(&temp_path).await.ok();
+            return Err(GuavaError::TooLarge(format!("uploads are limited to {} bytes", max_len)));
+        }
+
+        hasher.update(&buffer[..chunk_len]);
+        if let Err(e) = file.write_all(&buffer[..chunk_len]).await {
+            fs::remove_file(&temp_path).await.ok();
+            return Err(e.into());
+        }
+
+        chunk_len = match body.read(&mut buffer).await {
+            Ok(read) => read,
+            Err(e) => {
+                fs::remove_file(&temp_path).await.ok();
+                return Err(e.into());
+            }
+        };
+    }
+    let synced = file.sync_all().await;
+    drop(file);
+    if let Err(e) = synced {
+        fs::remove_file(&temp_path).await.ok();
+        return Err(e.into());
+    }
+
+    Ok((temp_path, hex::encode(hasher.finalize()), total_len))
+}
+
+/// Streams an upload into the blob store via a local staging file, so it can be
+/// stored under its hash once complete. Processing it is left to a background job.
+async fn store_upload(body: &mut Body, declared_type: GuavaContentType, store: &dyn BlobStore, config: &Config) -> Result<StoredUpload, GuavaError> {
+    let (staged, hash, size) = stage_upload(body, declared_type, config).await?;
+
+    let result = store.put(&hash, &staged).await;
+    // the store may already have moved the staged file into place
+    fs::remove_file(&staged).await.ok();
+    result?;
+
+    Ok(StoredUpload {
+        hash,
+        size,
+        mime_type: declared_type.mime_type().to_string(),
+        media: MediaInfo::default(),
+        variants: vec![],
+    })
+}
+
+/// List content
+///
+/// Lists a page of content, newest first. Content only reachable through private playlists
+/// the requester can't edit is left out unless they own it. Supports `type`, `state`,
+/// full-text search over names and tags with `q`, `min_size`/`max_size` in bytes,
+/// `created_after`/`created_before` as RFC 3339 timestamps, `limit`, and `after` set to
+/// the previous page's `next`.
+async fn list_content(req: Request<State>) -> tide::Result {
+    let content_service = &req.state().content_service;
+
+    let query = match req.query::<ContentListQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(error_response(GuavaError::BadRequest(String::from("invalid query"))).await),
+    };
+
+    let principal = match principal(&req) {
+        Ok(principal) => principal,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    // content only in other users' private playlists is left out
+    let hidden = match req.state().playlist_service.hidden_content_ids(&principal).await {
+        Ok(hidden) => hidden,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    match content_service.list_contents(query, hidden, &principal.id).await {
+        Ok(page) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(page).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Upload content
+///
+/// Streams the request body into the content directory under its SHA-256 hash
+/// and registers it as new content of the type given by the `type` query parameter.
+/// The content is `pending` until a background job has processed it; the job can be
+/// followed through `GET /jobs/:job_id`. If the job can't be queued the content is `failed`.
+async fn upload_content(mut req: Request<State>) -> tide::Result {
+    let query = match req.query::<UploadQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(error_response(GuavaError::BadRequest(String::from("missing or invalid content type"))).await),
+    };
+    let declared_type = query.content_type;
+    let metadata = ContentMetadata {
+        name: query.name,
+        description: query.description,
+        tags: query.tags.map(|tags| tags.split(',').map(String::from).collect()),
+        author: query.author,
+        license: query.license,
+        source_url: query.source_url,
+    };
+    if let Err(e) = metadata.validate() {
+        return Ok(error_response(e).await);
+    }
+
+    let owner = match principal(&req) {
+        Ok(principal) => principal.id,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let mut body = req.take_body();
+    let content_service = &req.state().content_service;
+    let upload = match store_upload(&mut body, declared_type, content_service.store(), &req.state().config).await {
+        Ok(upload) => upload,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let hash = upload.hash.clone();
+    let content_id = match content_service.create_content(declared_type, upload, metadata, owner).await {
+        Ok(content_id) => content_id,
+        Err(e) => {
+            if let Err(e) = content_service.discard_blob(&hash).await {
+                tide::log::error!("Failed to discard {} after failing to create its content: {}", hash, e);
+            }
+            return Ok(error_response(e).await);
+        },
+    };
+
+    match req.state().job_service.enqueue(JobKind::Process, Some(content_id.clone())).await {
+        Ok(job) => Ok(generate_response(StatusCode::Accepted, Some(json!({ "content_id": content_id, "state": "pending", "job_id": job.job_id })), None).await),
+        Err(e) => {
+            // without a job the content would stay pending forever
+            if let Err(e) = content_service.set_state(&content_id, ContentState::Failed, Some(String::from("processing could not be queued"))).await {
+                tide::log::error!("Failed to mark {} as failed: {}", content_id, e);
+            }
+            Ok(error_response(e).await)
+        },
+    }
+}
+
+/// Latest scrub report
+async fn get_scrub_report(req: Request<State>) -> tide::Result {
+    let scrub_service = &req.state().scrub_service;
+
+    match scrub_service.latest_report().await {
+        Ok(Some(report)) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(report).unwrap()), None).await),
+        Ok(None) => Ok(error_response(GuavaError::NotFound(String::from("no scrub has run yet"))).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Start scrub
+///
+/// Queues a scrub job; its report is available from `GET /admin/scrub` once done
+async fn start_scrub(req: Request<State>) -> tide::Result {
+    if req.state().scrub_service.is_running() {
+        return Ok(error_response(GuavaError::Conflict(String::from("scrub already running"))).await);
+    }
+
+    match req.state().job_service.enqueue(JobKind::Scrub, None).await {
+        Ok(job) => Ok(generate_response(StatusCode::Accepted, Some(serde_json::value::to_value(job).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
     }
 }
 
+/// List jobs
+///
+/// Lists a page of background jobs, newest first. Supports `status`, `kind`, `content_id`,
+/// `limit`, and `after` set to the previous page's `next`.
+async fn list_jobs(req: Request<State>) -> tide::Result {
+    let query = match req.query::<JobListQuery>() {
+        Ok(query) => query,
+        Err(_) => return Ok(error_response(GuavaError::BadRequest(String::from("invalid query"))).await),
+    };
+
+    match req.state().job_service.list(query).await {
+        Ok(page) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(page).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Job status
+async fn get_job(req: Request<State>) -> tide::Result {
+    match req.state().job_service.get(req.param("job_id").unwrap()).await {
+        Ok(job) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(job).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Retry job
+///
+/// Queues a failed job to run again
+async fn retry_job(req: Request<State>) -> tide::Result {
+    match req.state().ingest_service.retry(req.param("job_id").unwrap()).await {
+        Ok(job) => Ok(generate_response(StatusCode::Accepted, Some(serde_json::value::to_value(job).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Register user
+///
+/// Creates a user account; only admins may do so unless registration is open
+async fn register_user(mut req: Request<State>) -> tide::Result {
+    let request: RegisterRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+
+    let is_admin = principal(&req).is_ok_and(|principal| principal.role == Role::Admin);
+    if !is_admin && !req.state().config.auth.open_registration {
+        return Ok(error_response(GuavaError::Forbidden(String::from("registration is closed"))).await);
+    }
+    let role = match request.role {
+        Some(role) if is_admin => role,
+        _ => Role::Curator,
+    };
+
+    let user_service = &req.state().user_service;
+    match user_service.register(request.username, &request.password, role).await {
+        Ok(user) => Ok(generate_response(StatusCode::Created, Some(serde_json::value::to_value(user).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn get_current_user(req: Request<State>) -> tide::Result {
+    let principal = match principal(&req) {
+        Ok(principal) => principal,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let user_service = &req.state().user_service;
+
+    match user_service.get(&principal.id).await {
+        Ok(user) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(user).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Log in
+///
+/// Exchanges a username and password for a session token, used like an API key
+async fn login(mut req: Request<State>) -> tide::Result {
+    let request: LoginRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let user_service = &req.state().user_service;
+
+    match user_service.login(&request.username, &request.password).await {
+        Ok((token, session)) => Ok(generate_response(StatusCode::Created, Some(json!({
+            "token": token,
+            "expires_at": session.expires_at.timestamp_millis(),
+        })), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn logout(req: Request<State>) -> tide::Result {
+    let token = req.header("Authorization")
+        .and_then(|header| header.as_str().strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let user_service = &req.state().user_service;
+
+    match token {
+        Some(token) => match user_service.logout(&token).await {
+            Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+            Err(e) => Ok(error_response(e).await),
+        },
+        None => Ok(error_response(GuavaError::BadRequest(String::from("no session token given"))).await),
+    }
+}
+
+/// Mint API key
+///
+/// Creates an API key; the key itself is only ever returned here
+async fn mint_api_key(mut req: Request<State>) -> tide::Result {
+    let request: MintKeyRequest = match parse_body(&mut req).await {
+        Ok(request) => request,
+        Err(e) => return Ok(error_response(e).await),
+    };
+    let auth_service = &req.state().auth_service;
+
+    match auth_service.mint(request.name, request.role).await {
+        Ok((api_key, key)) => Ok(generate_response(StatusCode::Created, Some(json!({
+            "key_id": api_key.key_id,
+            "name": api_key.name,
+            "role": api_key.role,
+            "key": key,
+        })), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn list_api_keys(req: Request<State>) -> tide::Result {
+    let auth_service = &req.state().auth_service;
+
+    match auth_service.list().await {
+        Ok(keys) => Ok(generate_response(StatusCode::Ok, Some(serde_json::value::to_value(keys).unwrap()), None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+async fn revoke_api_key(req: Request<State>) -> tide::Result {
+    let auth_service = &req.state().auth_service;
+
+    match auth_service.revoke(req.param("key_id").unwrap()).await {
+        Ok(_) => Ok(generate_response(StatusCode::Ok, None, None).await),
+        Err(e) => Ok(error_response(e).await),
+    }
+}
+
+/// Moves blobs from the old flat content directory into the sharded layout
+async fn migrate_layout(config: &Config) -> tide::Result<()> {
+    let store = LocalBlobStore::new(config.storage.root.clone());
+    let report = store.migrate_flat_layout().await?;
+
+    println!("Moved {} blobs into the sharded layout", report.moved);
+    for name in report.skipped.iter() {
+        println!("Skipped '{}': not a content hash", name);
+    }
+
+    Ok(())
+}
+
 /// Main function
 #[async_std::main] 
 async fn main() -> tide::Result<()> {
-    let mut db_client_options = match ClientOptions::parse(&*CONNECTION_STRING). await {
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Some(Command::MigrateLayout) = &cli.command {
+        return migrate_layout(&config).await;
+    }
+
+    let mut db_client_options = match ClientOptions::parse(&config.database.uri).await {
         Ok(client_options) => client_options,
-        Err(e) => panic!("Failed to generate client options! Reason: {}", e),
+        Err(e) => {
+            eprintln!("Invalid database uri: {}", e);
+            process::exit(1);
+        }
     };
     db_client_options.app_name = Some(String::from("Guava"));
+    if let (Some(username), Some(password)) = (&config.database.username, &config.database.password) {
+        db_client_options.credential = Some(Credential::builder()
+            .username(username.clone())
+            .password(password.clone())
+            .build());
+    }
 
     let db_client = match Client::with_options(db_client_options) {
         Ok(client) => client,
-        Err(e) => panic!("Failed to open connection to database! Reason: {}", e),
+        Err(e) => {
+            eprintln!("Failed to open connection to database: {}", e);
+            process::exit(1);
+        }
     };
+    let db = db_client.database(&config.database.name);
+    let auth_service = AuthService::new(db.clone());
+    let user_service = UserService::new(db.clone(), config.auth.session_ttl_secs);
 
+    if let Some(Command::MintKey { name, role }) = cli.command {
+        let (api_key, key) = auth_service.mint(name, role).await?;
+        println!("Minted {:?} key {}: {}", api_key.role, api_key.key_id, key);
+        return Ok(());
+    }
+
+    let store: Arc<dyn BlobStore> = match config.storage.backend {
+        StorageBackend::Local => Arc::new(LocalBlobStore::new(config.storage.root.clone())),
+        StorageBackend::S3 => {
+            let s3 = &config.storage.s3;
+            match S3BlobStore::new(&s3.endpoint, &s3.bucket, &s3.region, &s3.access_key, &s3.secret_key) {
+                Ok(store) => Arc::new(store),
+                Err(e) => {
+                    eprintln!("Failed to configure S3 storage: {}", e);
+                    process::exit(1);
+                }
+            }
+        },
+    };
+
+    let config = Arc::new(config);
+    let content_service = ContentService::new(db.clone(), store, config.storage.verify_on_read);
+    let scrub_service = ScrubService::new(db.clone(), content_service.clone());
+    let job_service = JobService::new(db.clone(), config.jobs.clone());
+    let ingest_service = IngestService::new(
+        config.clone(),
+        content_service.clone(),
+        job_service.clone(),
+        ThumbnailService::new(content_service.clone(), &config),
+        scrub_service.clone(),
+    );
     let state: State = State { 
-        db: db_client.database("guava"),
-        content_service: ContentService::new(db_client.database("guava"))
+        config: config.clone(),
+        auth_service,
+        user_service,
+        playlist_service: PlaylistService::new(db, content_service.clone()),
+        scrub_service,
+        job_service,
+        ingest_service,
+        content_service,
     };
+
+    state.job_service.start(Arc::new(state.ingest_service.clone()));
+
+    let content_service = state.content_service.clone();
+    task::spawn(async move {
+        if let Err(e) = content_service.ensure_indexes().await {
+            tide::log::error!("Failed to create content indexes: {}", e);
+        }
+    });
+
+    let auth_service = state.auth_service.clone();
+    task::spawn(async move {
+        if let Err(e) = auth_service.ensure_indexes().await {
+            tide::log::error!("Failed to create api key indexes: {}", e);
+        }
+    });
+
+    let user_service = state.user_service.clone();
+    task::spawn(async move {
+        if let Err(e) = user_service.ensure_indexes().await {
+            tide::log::error!("Failed to create user indexes: {}", e);
+        }
+    });
+
+    let job_service = state.job_service.clone();
+    task::spawn(async move {
+        if let Err(e) = job_service.ensure_indexes().await {
+            tide::log::error!("Failed to create job indexes: {}", e);
+        }
+    });
+
+    let playlist_service = state.playlist_service.clone();
+    task::spawn(async move {
+        if let Err(e) = playlist_service.ensure_indexes().await {
+            tide::log::error!("Failed to create playlist indexes: {}", e);
+        }
+        match playlist_service.backfill_timestamps().await {
+            Ok(0) => {},
+            Ok(count) => tide::log::info!("Backfilled timestamps of {} playlists", count),
+            Err(e) => tide::log::error!("Failed to backfill playlist timestamps: {}", e),
+        }
+    });
+
+    let scrub_interval = config.scrub.interval_secs;
+    if scrub_interval > 0 {
+        let job_service = state.job_service.clone();
+        task::spawn(async move {
+            loop {
+                task::sleep(Duration::from_secs(scrub_interval)).await;
+                match job_service.enqueue(JobKind::Scrub, None).await {
+                    Ok(_) => {},
+                    // queued by another server, or still running from the last interval
+                    Err(GuavaError::Conflict(_)) => tide::log::debug!("Scrub already queued, not queueing another"),
+                    Err(e) => tide::log::error!("Failed to queue scrub: {}", e),
+                }
+            }
+        });
+    }
     
     let mut app = tide::with_state(state);
     app.with(tide::log::LogMiddleware::new()); 
 
-    tide::log::start();
+    tide::log::with_level(config.log_level_filter());
 
     // list playlists
 
     // index
     app.at("/").get(|_| async move { Ok(String::from("OK")) });
 
+    let anonymous_reads = config.auth.anonymous_reads;
+    let reader = || RequireRole::new(Role::Reader).allow_anonymous(anonymous_reads);
+    let curator = || RequireRole::new(Role::Curator);
+    let admin = || RequireRole::new(Role::Admin);
+
     // content
-    app.at("/content/:id/hash").get(get_hash_of_content);
-    app.at("/content/:id/download").get(download_asset);
+    app.at("/content").with(curator()).get(list_content).post(upload_content);
+    app.at("/content/hashes").with(reader()).post(lookup_hashes);
+    app.at("/content/:id").with(reader()).get(get_content);
+    app.at("/content/:id").with(curator()).patch(update_content);
+    app.at("/content/:id/hash").with(reader()).get(get_hash_of_content);
+    app.at("/content/:id/download").with(reader()).get(download_asset);
+    app.at("/content/:id/thumbnail").with(reader()).get(get_thumbnail);
 
     // playlist
-    app.at("/playlists").get(list_playlist);
+    app.at("/playlists").with(reader()).get(list_playlist);
+    app.at("/playlists").with(curator()).post(create_playlist);
+    app.at("/playlists/:identifier").with(reader()).get(get_playlist);
+    app.at("/playlists/:identifier").with(curator()).patch(update_playlist).delete(delete_playlist);
+    app.at("/playlists/:identifier/manifest").with(reader()).get(get_playlist_manifest);
+    app.at("/playlists/:identifier/bundle").with(reader()).get(get_playlist_bundle);
+    app.at("/playlists/:identifier/shares").with(curator()).get(list_share_tokens).post(create_share_token);
+    app.at("/playlists/:identifier/shares/:token_id").with(curator()).delete(revoke_share_token);
+    app.at("/playlists/:identifier/content").with(curator()).post(add_playlist_content);
+    app.at("/playlists/:identifier/content/order").with(curator()).put(reorder_playlist_content);
+    app.at("/playlists/:identifier/content/:content_id").with(curator()).delete(remove_playlist_content);
+
+    // users
+    app.at("/users").with(RequireRole::new(Role::Reader).allow_anonymous(true)).post(register_user);
+    app.at("/users/me").with(RequireRole::new(Role::Reader)).get(get_current_user);
+    app.at("/sessions").post(login);
+    app.at("/sessions").with(RequireRole::new(Role::Reader)).delete(logout);
+
+    // jobs
+    app.at("/jobs").with(curator()).get(list_jobs);
+    app.at("/jobs/:job_id").with(curator()).get(get_job);
+    app.at("/jobs/:job_id/retry").with(admin()).post(retry_job);
+
+    // admin
+    app.at("/admin/scrub").with(admin()).get(get_scrub_report).post(start_scrub);
+    app.at("/admin/keys").with(admin()).get(list_api_keys).post(mint_api_key);
+    app.at("/admin/keys/:key_id").with(admin()).delete(revoke_api_key);
 
-    app.listen("127.0.0.1:8080").await?;
+    app.listen(config.bind_address.clone()).await?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,187 @@
+use std::{convert::TryFrom, fs::File, io::{BufWriter, ErrorKind}, num::{NonZeroU8, NonZeroU32}, path::{Path, PathBuf}};
+use ebur128::{EbuR128, Mode};
+use symphonia::core::{audio::{AudioBuffer, AudioBufferRef, Signal}, codecs::{CODEC_TYPE_NULL, DecoderOptions}, errors::Error, formats::FormatOptions, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoder, VorbisEncoderBuilder};
+use crate::config::AudioConfig;
+use crate::error::GuavaError;
+use crate::media::{MediaInfo, Processed};
+
+fn undecodable(reason: &str) -> GuavaError {
+    GuavaError::Validation(format!("audio could not be decoded: {}", reason))
+}
+
+fn encode_failed(e: vorbis_rs::VorbisError) -> GuavaError {
+    GuavaError::Internal(format!("failed to encode audio: {}", e))
+}
+
+/// MIME type of an audio file from its signature
+fn mime_type(path: &Path) -> Result<&'static str, GuavaError> {
+    let mut header = [0u8; 4];
+    let read = std::io::Read::read(&mut File::open(path)?, &mut header)?;
+
+    Ok(match &header[..read] {
+        b"OggS" => "audio/ogg",
+        b"fLaC" => "audio/flac",
+        b"RIFF" => "audio/wav",
+        _ => "audio/mpeg",
+    })
+}
+
+/// Stream properties found while decoding
+pub struct Decoded {
+    pub codec: Option<String>,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub frames: u64,
+}
+
+/// Decodes the first audio track of a file in full, passing each block of samples to
+/// `on_block` as planar `f32`. Damaged packets are skipped, as players do.
+pub fn decode(path: &Path, mut on_block: impl FnMut(&AudioBuffer<f32>) -> Result<(), GuavaError>) -> Result<Decoded, GuavaError> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(&Hint::new(), source, &FormatOptions::default(), &MetadataOptions::default())
+        .map_err(|_| undecodable("unsupported format"))?;
+    let mut format = probed.format;
+
+    let track = format.tracks().iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| undecodable("no audio track"))?;
+    let track_id = track.id;
+    let params = track.codec_params.clone();
+
+    let codecs = symphonia::default::get_codecs();
+    let mut decoder = codecs.make(&params, &DecoderOptions::default()).map_err(|_| undecodable("unsupported codec"))?;
+    let codec = codecs.get_codec(params.codec).map(|descriptor| descriptor.short_name.to_string());
+
+    let mut frames = 0u64;
+    let mut samples: Option<AudioBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(_) => return Err(undecodable("corrupt stream")),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded: AudioBufferRef = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            Err(Error::DecodeError(_)) => continue,
+            Err(_) => return Err(undecodable("corrupt stream")),
+        };
+        if decoded.frames() == 0 {
+            continue;
+        }
+
+        let spec = *decoded.spec();
+        if samples.as_ref().is_none_or(|buffer| buffer.capacity() < decoded.capacity() || *buffer.spec() != spec) {
+            samples = Some(AudioBuffer::new(decoded.capacity() as u64, spec));
+        }
+        let buffer = samples.as_mut().unwrap();
+        decoded.convert(buffer);
+
+        frames += buffer.frames() as u64;
+        on_block(buffer)?;
+    }
+
+    match samples {
+        Some(buffer) if frames > 0 && buffer.spec().rate > 0 => Ok(Decoded {
+            codec,
+            sample_rate: buffer.spec().rate,
+            channels: buffer.spec().channels.count() as u32,
+            frames,
+        }),
+        _ => Err(undecodable("no audio")),
+    }
+}
+
+/// Re-encodes audio as OGG Vorbis at the given quality, scaling samples by `gain`
+fn transcode(path: &Path, output: &Path, quality: f32, gain: f32) -> Result<(), GuavaError> {
+    let mut encoder: Option<VorbisEncoder<BufWriter<File>>> = None;
+    let mut scaled: Vec<Vec<f32>> = vec![];
+
+    let result = decode(path, |buffer| {
+        if encoder.is_none() {
+            let spec = buffer.spec();
+            let rate = NonZeroU32::new(spec.rate).ok_or_else(|| undecodable("no sample rate"))?;
+            let channels = u8::try_from(spec.channels.count()).ok().and_then(NonZeroU8::new).ok_or_else(|| undecodable("unsupported channel count"))?;
+
+            encoder = Some(VorbisEncoderBuilder::new(rate, channels, BufWriter::new(File::create(output)?))
+                .map_err(encode_failed)?
+                .bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr { target_quality: quality })
+                .build()
+                .map_err(encode_failed)?);
+        }
+
+        scaled.resize(buffer.spec().channels.count(), vec![]);
+        for (channel, samples) in scaled.iter_mut().enumerate() {
+            samples.clear();
+            samples.extend(buffer.chan(channel).iter().map(|sample| (sample * gain).clamp(-1.0, 1.0)));
+        }
+        encoder.as_mut().unwrap().encode_audio_block(&scaled).map_err(encode_failed)?;
+        Ok(())
+    });
+
+    let finished = match (result, encoder) {
+        (Ok(_), Some(encoder)) => encoder.finish().map(|_| ()).map_err(encode_failed),
+        (Ok(_), None) => Err(undecodable("no audio")),
+        (Err(e), _) => Err(e),
+    };
+    if finished.is_err() {
+        std::fs::remove_file(output).ok();
+    }
+
+    finished
+}
+
+/// Decodes an OGG Vorbis, MP3, WAV or FLAC file in full, measuring its duration and
+/// integrated loudness; files that cannot be decoded are rejected. Depending on the
+/// config, audio is then transcoded to OGG Vorbis, keeping the original alongside.
+pub fn process(path: PathBuf, config: AudioConfig) -> Result<Processed, GuavaError> {
+    let mut meter: Option<EbuR128> = None;
+    let decoded = decode(&path, |buffer| {
+        let spec = buffer.spec();
+        if meter.is_none() {
+            meter = EbuR128::new(spec.channels.count() as u32, spec.rate, Mode::I).ok();
+        }
+        if let Some(meter) = meter.as_mut() {
+            meter.add_frames_planar_f32(buffer.planes().planes()).ok();
+        }
+        Ok(())
+    })?;
+
+    // silence has no measurable loudness
+    let loudness = meter.and_then(|meter| meter.loudness_global().ok()).filter(|loudness| loudness.is_finite());
+    let mut info = MediaInfo {
+        audio_codec: decoded.codec.clone(),
+        sample_rate: Some(decoded.sample_rate),
+        channels: Some(decoded.channels),
+        duration_secs: Some(decoded.frames as f64 / decoded.sample_rate as f64),
+        loudness_lufs: loudness,
+        ..MediaInfo::default()
+    };
+
+    let gain_db = match (config.normalize, loudness) {
+        (true, Some(loudness)) => config.target_loudness_lufs - loudness,
+        _ => 0.0,
+    };
+    let original_mime_type = mime_type(&path)?;
+    let is_vorbis = decoded.codec.as_deref() == Some("vorbis");
+    if !config.transcode || (is_vorbis && gain_db == 0.0) {
+        return Ok(Processed::new(original_mime_type, info));
+    }
+
+    let output = path.with_extension("ogg");
+    transcode(&path, &output, config.quality, 10f32.powf(gain_db as f32 / 20.0))?;
+
+    info.audio_codec = Some(String::from("vorbis"));
+    info.loudness_lufs = loudness.map(|loudness| loudness + gain_db);
+    Ok(Processed {
+        mime_type: "audio/ogg",
+        info,
+        converted: Some(output.into()),
+        original_mime_type: Some(original_mime_type),
+    })
+}
@@ -0,0 +1,65 @@
+use std::{fs::File, io::BufReader, path::{Path, PathBuf}};
+use ::image::{ImageFormat, ImageReader};
+use crate::config::ImageConfig;
+use crate::error::GuavaError;
+use crate::media::{MediaInfo, Processed};
+
+/// Detects the format of an image; TGA has no signature, so anything else is assumed to be one
+fn detect_format(path: &Path) -> Result<ImageFormat, GuavaError> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+
+    match reader.format() {
+        Some(format @ (ImageFormat::Png | ImageFormat::Jpeg)) => Ok(format),
+        Some(_) => Err(GuavaError::Validation(String::from("images must be PNG, JPEG or TGA"))),
+        None => Ok(ImageFormat::Tga),
+    }
+}
+
+fn invalid_image() -> GuavaError {
+    GuavaError::Validation(String::from("image could not be decoded"))
+}
+
+/// Decodes an image to check it is a real PNG, JPEG or TGA image within the configured
+/// dimensions, converting it to PNG if configured to.
+pub fn process(path: PathBuf, config: ImageConfig) -> Result<Processed, GuavaError> {
+    let format = detect_format(&path)?;
+    let open = || -> Result<ImageReader<BufReader<File>>, GuavaError> {
+        let mut reader = ImageReader::new(BufReader::new(File::open(&path)?));
+        reader.set_format(format);
+        Ok(reader)
+    };
+
+    // check the header before decoding so oversized images are never allocated
+    let (width, height) = open()?.into_dimensions().map_err(|_| invalid_image())?;
+    if width > config.max_width || height > config.max_height {
+        return Err(GuavaError::Validation(format!("images are limited to {}x{} pixels", config.max_width, config.max_height)));
+    }
+
+    let image = open()?.decode().map_err(|_| invalid_image())?;
+    let info = MediaInfo {
+        format: format.extensions_str().first().map(|extension| extension.to_string()),
+        width: Some(width),
+        height: Some(height),
+        ..MediaInfo::default()
+    };
+
+    if !config.convert_to_png || format == ImageFormat::Png {
+        return Ok(Processed::new(format.to_mime_type(), info));
+    }
+
+    let converted = path.with_extension("png");
+    if let Err(e) = image.save_with_format(&converted, ImageFormat::Png) {
+        std::fs::remove_file(&converted).ok();
+        return Err(GuavaError::Internal(format!("failed to convert image: {}", e)));
+    }
+
+    Ok(Processed {
+        mime_type: ImageFormat::Png.to_mime_type(),
+        info: MediaInfo {
+            format: Some(String::from("png")),
+            ..info
+        },
+        converted: Some(converted.into()),
+        original_mime_type: Some(format.to_mime_type()),
+    })
+}
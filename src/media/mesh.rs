@@ -0,0 +1,263 @@
+use std::{fs::{self, File}, io::{BufRead, BufReader, Read}, path::Path};
+use crate::error::GuavaError;
+use crate::media::{MediaInfo, Processed};
+
+/// Longest first line of a Roblox mesh, e.g. `version 4.00`
+const MAX_VERSION_LINE: u64 = 16;
+
+fn malformed(reason: &str) -> GuavaError {
+    GuavaError::Validation(format!("malformed mesh: {}", reason))
+}
+
+fn u16_at(header: &[u8], offset: usize) -> u64 {
+    u16::from_le_bytes([header[offset], header[offset + 1]]) as u64
+}
+
+fn u32_at(header: &[u8], offset: usize) -> u64 {
+    u32::from_le_bytes([header[offset], header[offset + 1], header[offset + 2], header[offset + 3]]) as u64
+}
+
+/// Reads the header of a binary mesh, which starts with its own size
+fn read_header(reader: &mut impl Read, min_len: usize) -> Result<Vec<u8>, GuavaError> {
+    let mut size = [0u8; 2];
+    reader.read_exact(&mut size).map_err(|_| malformed("truncated header"))?;
+
+    let header_len = u16::from_le_bytes(size) as usize;
+    if header_len < min_len {
+        return Err(malformed("header too short"));
+    }
+
+    let mut header = vec![0u8; header_len];
+    header[..2].copy_from_slice(&size);
+    reader.read_exact(&mut header[2..]).map_err(|_| malformed("truncated header"))?;
+    Ok(header)
+}
+
+/// Checks the face lines of a version 1 text mesh, returning the face count
+fn parse_text_mesh(reader: &mut impl BufRead) -> Result<u64, GuavaError> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|_| malformed("invalid face count"))?;
+    let faces: u64 = line.trim().parse().map_err(|_| malformed("invalid face count"))?;
+
+    // each face is three vertices of position, normal and texture coordinate vectors
+    let mut vectors = 0u64;
+    for line in reader.lines() {
+        let line = line.map_err(|_| malformed("not text"))?;
+        vectors += line.matches('[').count() as u64;
+    }
+
+    match vectors == faces * 9 {
+        true => Ok(faces),
+        false => Err(malformed("face count does not match data")),
+    }
+}
+
+/// Length a binary mesh must have according to its header, and its vertex and face counts
+fn binary_mesh_len(version: &str, header: &[u8]) -> Option<(u64, u64, u64)> {
+    let header_len = header.len() as u64;
+
+    match version {
+        "2.00" => {
+            let (vertex_size, face_size, vertices, faces) = (header[2] as u64, header[3] as u64, u32_at(header, 4), u32_at(header, 8));
+            Some((header_len + vertices * vertex_size + faces * face_size, vertices, faces))
+        },
+        "3.00" | "3.01" => {
+            let (vertex_size, face_size, lod_size, lods) = (header[2] as u64, header[3] as u64, u16_at(header, 4), u16_at(header, 6));
+            let (vertices, faces) = (u32_at(header, 8), u32_at(header, 12));
+            Some((header_len + vertices * vertex_size + faces * face_size + lods * lod_size, vertices, faces))
+        },
+        "4.00" | "4.01" | "5.00" => {
+            let (vertices, faces, lods, bones) = (u32_at(header, 4), u32_at(header, 8), u16_at(header, 12), u16_at(header, 14));
+            let (bone_names_len, subsets) = (u32_at(header, 16), u16_at(header, 20));
+            let envelopes = if bones > 0 { vertices * 8 } else { 0 };
+            let facs_len = if version == "5.00" { u32_at(header, 28) } else { 0 };
+
+            Some((header_len + vertices * 40 + envelopes + faces * 12 + lods * 4 + bones * 60 + bone_names_len + subsets * 72 + facs_len, vertices, faces))
+        },
+        _ => None,
+    }
+}
+
+/// Parses a Roblox mesh, versions 1.00 to 5.00
+fn process_roblox_mesh(path: &Path) -> Result<Processed, GuavaError> {
+    let file_len = fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut line = Vec::new();
+    (&mut reader).take(MAX_VERSION_LINE).read_until(b'\n', &mut line)?;
+    let version_line = String::from_utf8_lossy(&line);
+    let version = version_line.trim().strip_prefix("version ").ok_or_else(|| malformed("missing version"))?.to_string();
+
+    let (vertices, faces) = match version.as_str() {
+        "1.00" | "1.01" => {
+            let faces = parse_text_mesh(&mut reader)?;
+            (faces * 3, faces)
+        },
+        _ => {
+            let min_header_len = match version.as_str() {
+                "2.00" => 12,
+                "3.00" | "3.01" => 16,
+                "4.00" | "4.01" => 24,
+                "5.00" => 32,
+                _ => return Err(GuavaError::Validation(format!("unsupported mesh version '{}'", version))),
+            };
+            let header = read_header(&mut reader, min_header_len)?;
+
+            let (expected_len, vertices, faces) = binary_mesh_len(&version, &header).unwrap();
+            if file_len < line.len() as u64 + expected_len {
+                return Err(malformed("truncated data"));
+            }
+            (vertices, faces)
+        },
+    };
+
+    Ok(Processed::new("application/octet-stream", MediaInfo {
+        format: Some(format!("mesh {}", version)),
+        vertex_count: Some(vertices),
+        face_count: Some(faces),
+        ..MediaInfo::default()
+    }))
+}
+
+/// Checks a face's vertex references are in range
+fn check_face(indices: std::str::SplitWhitespace, vertices: u64) -> Result<(), GuavaError> {
+    let mut corners = 0;
+    for index in indices {
+        let index: i64 = index.split('/').next().unwrap_or_default().parse().map_err(|_| malformed("invalid face"))?;
+
+        // negative indices count back from the latest vertex
+        let valid = match index {
+            0 => false,
+            index if index > 0 => index as u64 <= vertices,
+            index => index.unsigned_abs() <= vertices,
+        };
+        if !valid {
+            return Err(malformed("face references a missing vertex"));
+        }
+        corners += 1;
+    }
+
+    match corners >= 3 {
+        true => Ok(()),
+        false => Err(malformed("face has fewer than three vertices")),
+    }
+}
+
+/// Parses a Wavefront OBJ mesh
+fn process_obj(path: &Path) -> Result<Processed, GuavaError> {
+    let reader = BufReader::new(File::open(path)?);
+    let (mut vertices, mut faces) = (0u64, 0u64);
+
+    for line in reader.lines() {
+        let line = line.map_err(|_| malformed("not text"))?;
+        let mut fields = line.split_whitespace();
+
+        match fields.next() {
+            Some("v") => {
+                let coordinates = fields.take(3).filter(|value| value.parse::<f64>().is_ok()).count();
+                if coordinates != 3 {
+                    return Err(malformed("invalid vertex"));
+                }
+                vertices += 1;
+            },
+            Some("f") => {
+                check_face(fields, vertices)?;
+                faces += 1;
+            },
+            _ => {},
+        }
+    }
+
+    if vertices == 0 || faces == 0 {
+        return Err(malformed("no geometry"));
+    }
+
+    Ok(Processed::new("model/obj", MediaInfo {
+        format: Some(String::from("obj")),
+        vertex_count: Some(vertices),
+        face_count: Some(faces),
+        ..MediaInfo::default()
+    }))
+}
+
+/// Validates a Roblox mesh or OBJ file, extracting its vertex and face counts
+pub fn process(path: &Path) -> Result<Processed, GuavaError> {
+    let mut signature = [0u8; 8];
+    let read = File::open(path)?.read(&mut signature)?;
+
+    match signature[..read].starts_with(b"version ") {
+        true => process_roblox_mesh(path),
+        false => process_obj(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of a version 2.00 mesh with 36 byte vertices and 12 byte faces
+    fn header_v2(vertices: u32, faces: u32) -> Vec<u8> {
+        let mut header = vec![12, 0, 36, 12];
+        header.extend_from_slice(&vertices.to_le_bytes());
+        header.extend_from_slice(&faces.to_le_bytes());
+        header
+    }
+
+    fn process_bytes(name: &str, bytes: &[u8]) -> Result<Processed, GuavaError> {
+        let path = std::env::temp_dir().join(format!("guava-mesh-test-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let processed = process(&path);
+        fs::remove_file(&path).ok();
+        processed
+    }
+
+    #[test]
+    fn binary_lengths_follow_the_header() {
+        assert_eq!(binary_mesh_len("2.00", &header_v2(3, 1)), Some((12 + 3 * 36 + 12, 3, 1)));
+
+        let mut header_v3 = vec![16, 0, 40, 12, 4, 0, 2, 0];
+        header_v3.extend_from_slice(&4u32.to_le_bytes());
+        header_v3.extend_from_slice(&2u32.to_le_bytes());
+        assert_eq!(binary_mesh_len("3.00", &header_v3), Some((16 + 4 * 40 + 2 * 12 + 2 * 4, 4, 2)));
+
+        let mut header_v4 = vec![0u8; 24];
+        header_v4[0] = 24;
+        header_v4[4..8].copy_from_slice(&4u32.to_le_bytes());
+        header_v4[8..12].copy_from_slice(&2u32.to_le_bytes());
+        header_v4[12..14].copy_from_slice(&2u16.to_le_bytes());
+        header_v4[14..16].copy_from_slice(&1u16.to_le_bytes());
+        header_v4[16..20].copy_from_slice(&5u32.to_le_bytes());
+        header_v4[20..22].copy_from_slice(&1u16.to_le_bytes());
+        // bones add an envelope per vertex
+        assert_eq!(binary_mesh_len("4.00", &header_v4), Some((24 + 4 * 40 + 4 * 8 + 2 * 12 + 2 * 4 + 60 + 5 + 72, 4, 2)));
+
+        assert_eq!(binary_mesh_len("6.00", &header_v2(3, 1)), None);
+    }
+
+    #[test]
+    fn complete_binary_mesh_is_accepted() {
+        let mut mesh = b"version 2.00\n".to_vec();
+        mesh.extend(header_v2(3, 1));
+        mesh.resize(mesh.len() + 3 * 36 + 12, 0);
+
+        let processed = process_bytes("complete", &mesh).unwrap();
+        assert_eq!(processed.info.vertex_count, Some(3));
+        assert_eq!(processed.info.face_count, Some(1));
+    }
+
+    #[test]
+    fn truncated_binary_meshes_are_rejected() {
+        let mut mesh = b"version 2.00\n".to_vec();
+        mesh.extend(header_v2(3, 1));
+        mesh.resize(mesh.len() + 3 * 36 + 12, 0);
+
+        // missing data, a partial header, and a header shorter than the version needs
+        for (name, bytes) in [("data", &mesh[..mesh.len() - 1]), ("header", &mesh[..18]), ("size", &mesh[..14])] {
+            assert!(matches!(process_bytes(name, bytes), Err(GuavaError::Validation(_))), "truncated {}", name);
+        }
+
+        let mut short_header = b"version 2.00\n".to_vec();
+        short_header.extend_from_slice(&[4, 0, 36, 12]);
+        assert!(matches!(process_bytes("short", &short_header), Err(GuavaError::Validation(_))));
+    }
+}
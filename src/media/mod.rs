@@ -0,0 +1,120 @@
+//! Validation and normalisation of uploaded media
+
+pub mod audio;
+pub mod image;
+pub mod mesh;
+pub mod model;
+pub mod thumbnail;
+pub mod video;
+
+use async_std::{fs::File, io::ReadExt, path::{Path, PathBuf}, task};
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::config::Config;
+use crate::error::GuavaError;
+use crate::service::content_service::GuavaContentType;
+
+/// Properties of decoded media, stored alongside content
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MediaInfo {
+    /// Format the content was recognised as, e.g. `mesh 4.00`, `rbxmx` or `webm`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Pixel dimensions of images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vertex_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub face_count: Option<u64>,
+    /// Number of instances in a model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance_count: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    /// Integrated loudness (EBU R128) of audio
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness_lufs: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<String>,
+    /// Codec of a sound, or of a video's audio track
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<f64>,
+    /// Whether Roblox can play the video
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playable: Option<bool>,
+}
+
+/// Outcome of processing an upload
+pub struct Processed {
+    pub mime_type: &'static str,
+    pub info: MediaInfo,
+    /// File the upload was converted into, replacing it
+    pub converted: Option<PathBuf>,
+    /// Keep the upload as the `original` variant of converted content, with this MIME type
+    pub original_mime_type: Option<&'static str>,
+}
+
+impl Processed {
+    fn new(mime_type: &'static str, info: MediaInfo) -> Self {
+        Processed {
+            mime_type,
+            info,
+            converted: None,
+            original_mime_type: None,
+        }
+    }
+}
+
+/// Validates a staged upload of the given type, extracting its metadata and normalising it
+/// where configured to. Types without a parser are left as they are.
+pub async fn process(path: &Path, content_type: GuavaContentType, config: &Config) -> Result<Option<Processed>, GuavaError> {
+    let std_path: &std::path::Path = path.as_ref();
+    let std_path = std_path.to_path_buf();
+
+    let processed = match content_type {
+        GuavaContentType::Image => {
+            let images = config.images.clone();
+            task::spawn_blocking(move || image::process(std_path, images)).await?
+        },
+        GuavaContentType::Sound => {
+            let audio = config.audio.clone();
+            task::spawn_blocking(move || audio::process(std_path, audio)).await?
+        },
+        GuavaContentType::Video => {
+            let video = config.video.clone();
+            task::spawn_blocking(move || video::process(&std_path, &video)).await?
+        },
+        GuavaContentType::Mesh => task::spawn_blocking(move || mesh::process(&std_path)).await?,
+        GuavaContentType::Model => task::spawn_blocking(move || model::process(&std_path)).await?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(processed))
+}
+
+/// SHA-256 hash and size of a file produced by processing an upload
+pub async fn hash_file(path: &Path) -> Result<(String, u64), GuavaError> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut len = 0u64;
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        len += read as u64;
+    }
+
+    Ok((hex::encode(hasher.finalize()), len))
+}
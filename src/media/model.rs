@@ -0,0 +1,149 @@
+use std::{fs::{self, File}, io::{BufRead, BufReader, Read, Seek, SeekFrom}, path::Path};
+use crate::error::GuavaError;
+use crate::media::{MediaInfo, Processed};
+
+/// Start of every binary model file, `<roblox!` followed by a line ending check
+const BINARY_SIGNATURE: &[u8] = b"<roblox!\x89\xff\r\n\x1a\n";
+const BINARY_HEADER_LEN: usize = 32;
+const CHUNK_HEADER_LEN: u64 = 16;
+
+fn malformed(reason: &str) -> GuavaError {
+    GuavaError::Validation(format!("malformed model: {}", reason))
+}
+
+/// Parses a binary `.rbxm` model, walking its chunks up to the `END` chunk
+fn process_binary(path: &Path) -> Result<Processed, GuavaError> {
+    let file_len = fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut header = [0u8; BINARY_HEADER_LEN];
+    reader.read_exact(&mut header).map_err(|_| malformed("truncated header"))?;
+    if !header.starts_with(BINARY_SIGNATURE) {
+        return Err(malformed("invalid signature"));
+    }
+    if u16::from_le_bytes([header[14], header[15]]) != 0 {
+        return Err(malformed("unsupported version"));
+    }
+
+    let instances = i32::from_le_bytes([header[20], header[21], header[22], header[23]]);
+    if instances < 0 {
+        return Err(malformed("negative instance count"));
+    }
+
+    let mut position = BINARY_HEADER_LEN as u64;
+    loop {
+        let mut chunk = [0u8; CHUNK_HEADER_LEN as usize];
+        reader.read_exact(&mut chunk).map_err(|_| malformed("missing END chunk"))?;
+
+        let compressed_len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        let uncompressed_len = u32::from_le_bytes([chunk[8], chunk[9], chunk[10], chunk[11]]) as u64;
+        let body_len = if compressed_len > 0 { compressed_len } else { uncompressed_len };
+
+        position += CHUNK_HEADER_LEN + body_len;
+        if position > file_len {
+            return Err(malformed("truncated chunk"));
+        }
+        if &chunk[0..4] == b"END\0" {
+            break;
+        }
+        reader.seek(SeekFrom::Start(position))?;
+    }
+
+    Ok(Processed::new("application/octet-stream", MediaInfo {
+        format: Some(String::from("rbxm")),
+        instance_count: Some(instances as u64),
+        ..MediaInfo::default()
+    }))
+}
+
+/// Parses an XML `.rbxmx` model, counting its items
+fn process_xml(path: &Path) -> Result<Processed, GuavaError> {
+    let reader = BufReader::new(File::open(path)?);
+    let (mut instances, mut closed) = (0u64, false);
+
+    for line in reader.lines() {
+        let line = line.map_err(|_| malformed("not text"))?;
+        instances += line.matches("<Item ").count() as u64;
+        closed |= line.contains("</roblox>");
+    }
+
+    if !closed {
+        return Err(malformed("missing closing roblox tag"));
+    }
+
+    Ok(Processed::new("application/xml", MediaInfo {
+        format: Some(String::from("rbxmx")),
+        instance_count: Some(instances),
+        ..MediaInfo::default()
+    }))
+}
+
+/// Validates a binary or XML Roblox model, extracting its instance count
+pub fn process(path: &Path) -> Result<Processed, GuavaError> {
+    let mut signature = [0u8; 8];
+    let read = File::open(path)?.read(&mut signature)?;
+
+    match &signature[..read] {
+        b"<roblox!" => process_binary(path),
+        signature if signature.starts_with(b"<roblox") => process_xml(path),
+        _ => Err(malformed("not a Roblox model")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = name.to_vec();
+        chunk.extend_from_slice(&0u32.to_le_bytes());
+        chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&[0u8; 4]);
+        chunk.extend_from_slice(body);
+        chunk
+    }
+
+    /// Binary model of `instances` instances with one INST chunk
+    fn binary_model(instances: i32) -> Vec<u8> {
+        let mut model = BINARY_SIGNATURE.to_vec();
+        model.extend_from_slice(&0u16.to_le_bytes());
+        model.extend_from_slice(&1i32.to_le_bytes());
+        model.extend_from_slice(&instances.to_le_bytes());
+        model.resize(BINARY_HEADER_LEN, 0);
+        model.extend(chunk(b"INST", &[0u8; 12]));
+        model.extend(chunk(b"END\0", b"</roblox>"));
+        model
+    }
+
+    fn process_bytes(name: &str, bytes: &[u8]) -> Result<Processed, GuavaError> {
+        let path = std::env::temp_dir().join(format!("guava-model-test-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        let processed = process(&path);
+        fs::remove_file(&path).ok();
+        processed
+    }
+
+    #[test]
+    fn complete_binary_model_is_accepted() {
+        let processed = process_bytes("complete", &binary_model(3)).unwrap();
+        assert_eq!(processed.info.format.as_deref(), Some("rbxm"));
+        assert_eq!(processed.info.instance_count, Some(3));
+    }
+
+    #[test]
+    fn truncated_binary_models_are_rejected() {
+        let model = binary_model(3);
+        let end_chunk = model.len() - CHUNK_HEADER_LEN as usize - 9;
+
+        // within the header, within a chunk header, before the END chunk and within its body
+        for len in [20, BINARY_HEADER_LEN + 8, end_chunk, model.len() - 1] {
+            let result = process_bytes(&len.to_string(), &model[..len]);
+            assert!(matches!(result, Err(GuavaError::Validation(_))), "truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn negative_instance_counts_are_rejected() {
+        assert!(matches!(process_bytes("negative", &binary_model(-1)), Err(GuavaError::Validation(_))));
+    }
+}
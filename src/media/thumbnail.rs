@@ -0,0 +1,127 @@
+use std::{fs::File, io::BufReader, path::{Path, PathBuf}};
+use ::image::{DynamicImage, ImageFormat, ImageReader, Rgba, RgbaImage};
+use matroska_demuxer::{Frame, MatroskaFile, TrackType};
+use symphonia::core::audio::Signal;
+use crate::error::GuavaError;
+use crate::media::audio;
+use crate::service::content_service::GuavaContentType;
+
+/// Blocks of audio frames each waveform peak is taken over
+const PEAK_BLOCK_FRAMES: usize = 256;
+const WAVEFORM_COLOUR: Rgba<u8> = Rgba([0x4c, 0xaf, 0x50, 0xff]);
+
+fn unreadable(reason: &str) -> GuavaError {
+    GuavaError::Validation(format!("no preview available: {}", reason))
+}
+
+/// Downscales an image to fit within `max_size` pixels
+fn image_thumbnail(path: &Path, max_size: u32) -> Result<DynamicImage, GuavaError> {
+    let image = ImageReader::open(path)?.with_guessed_format()?.decode().map_err(|_| unreadable("image could not be decoded"))?;
+    Ok(image.thumbnail(max_size, max_size))
+}
+
+/// Draws the peak amplitude of audio over time
+fn waveform(path: &Path, max_size: u32) -> Result<DynamicImage, GuavaError> {
+    let mut peaks: Vec<f32> = vec![];
+    let mut block_peak = 0f32;
+    let mut block_frames = 0;
+
+    audio::decode(path, |buffer| {
+        let channels = buffer.spec().channels.count();
+        for frame in 0..buffer.frames() {
+            for channel in 0..channels {
+                block_peak = block_peak.max(buffer.chan(channel)[frame].abs());
+            }
+
+            block_frames += 1;
+            if block_frames == PEAK_BLOCK_FRAMES {
+                peaks.push(block_peak);
+                block_peak = 0.0;
+                block_frames = 0;
+            }
+        }
+        Ok(())
+    })?;
+    if block_frames > 0 {
+        peaks.push(block_peak);
+    }
+
+    let (width, height) = (max_size, (max_size / 4).max(16));
+    let mut image = RgbaImage::new(width, height);
+    let middle = height as f32 / 2.0;
+
+    for x in 0..width {
+        let start = peaks.len() * x as usize / width as usize;
+        let end = (peaks.len() * (x as usize + 1) / width as usize).max(start + 1).min(peaks.len());
+        let peak = peaks.get(start..end).map_or(0.0, |peaks| peaks.iter().cloned().fold(0.0, f32::max)).min(1.0);
+
+        let extent = (peak * middle).max(0.5);
+        let (top, bottom) = ((middle - extent).max(0.0) as u32, ((middle + extent) as u32).min(height));
+        for y in top..bottom {
+            image.put_pixel(x, y, WAVEFORM_COLOUR);
+        }
+    }
+
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+/// Decodes the first keyframe of a VP8 WebM video; a VP8 keyframe is a lossy WebP image.
+///
+/// Other containers and codecs can't be decoded, leaving those videos without a preview.
+fn poster_frame(path: &Path, max_size: u32) -> Result<Option<DynamicImage>, GuavaError> {
+    // MP4 videos, which passed processing, are not Matroska
+    let mut file = match MatroskaFile::open(BufReader::new(File::open(path)?)) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    let video = match file.tracks().iter().find(|track| track.track_type() == TrackType::Video) {
+        Some(video) if video.codec_id() == "V_VP8" => video,
+        _ => return Ok(None),
+    };
+    let track = video.track_number().get();
+
+    let mut frame = Frame::default();
+    loop {
+        if !file.next_frame(&mut frame).map_err(|_| unreadable("invalid container"))? {
+            return Err(unreadable("no keyframe"));
+        }
+        if frame.track == track && frame.is_keyframe != Some(false) {
+            break;
+        }
+    }
+
+    let mut webp = Vec::with_capacity(frame.data.len() + 21);
+    let padding = frame.data.len() % 2;
+    webp.extend_from_slice(b"RIFF");
+    webp.extend_from_slice(&((frame.data.len() + padding + 12) as u32).to_le_bytes());
+    webp.extend_from_slice(b"WEBPVP8 ");
+    webp.extend_from_slice(&(frame.data.len() as u32).to_le_bytes());
+    webp.extend_from_slice(&frame.data);
+    webp.resize(webp.len() + padding, 0);
+
+    let image = ::image::load_from_memory_with_format(&webp, ImageFormat::WebP).map_err(|_| unreadable("frame could not be decoded"))?;
+    Ok(Some(image.thumbnail(max_size, max_size)))
+}
+
+/// Renders a PNG preview of a blob next to it: a downscaled image, a sound's waveform or
+/// a video's first frame. Returns `None` for types without previews and videos that can't
+/// be decoded.
+pub fn generate(path: &Path, content_type: GuavaContentType, max_size: u32) -> Result<Option<PathBuf>, GuavaError> {
+    let preview = match content_type {
+        GuavaContentType::Image => image_thumbnail(path, max_size)?,
+        GuavaContentType::Sound => waveform(path, max_size)?,
+        GuavaContentType::Video => match poster_frame(path, max_size)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    let output = path.with_extension("thumbnail.png");
+    if let Err(e) = preview.save_with_format(&output, ImageFormat::Png) {
+        std::fs::remove_file(&output).ok();
+        return Err(GuavaError::Internal(format!("failed to save preview: {}", e)));
+    }
+
+    Ok(Some(output))
+}
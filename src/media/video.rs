@@ -0,0 +1,100 @@
+use std::{fs::File, io::BufReader, path::Path};
+use matroska_demuxer::{MatroskaFile, TrackType};
+use crate::config::VideoConfig;
+use crate::error::GuavaError;
+use crate::media::{MediaInfo, Processed};
+
+/// Video and audio codecs Roblox's VideoFrame can play, as Matroska codec ids
+const PLAYABLE_VIDEO_CODECS: [&str; 2] = ["V_VP8", "V_VP9"];
+const PLAYABLE_AUDIO_CODECS: [&str; 2] = ["A_VORBIS", "A_OPUS"];
+
+fn malformed(reason: &str) -> GuavaError {
+    GuavaError::Validation(format!("malformed video: {}", reason))
+}
+
+/// Lowercase codec name from a Matroska codec id, e.g. `V_VP9` becomes `vp9`
+fn codec_name(codec_id: &str) -> String {
+    codec_id.split_once('_').map_or(codec_id, |(_, name)| name).to_lowercase()
+}
+
+/// Reads the tracks of a WebM or Matroska file
+fn probe_matroska(path: &Path) -> Result<MediaInfo, GuavaError> {
+    let file = MatroskaFile::open(BufReader::new(File::open(path)?)).map_err(|_| malformed("invalid matroska container"))?;
+
+    let video = file.tracks().iter().find(|track| track.track_type() == TrackType::Video).ok_or_else(|| malformed("no video track"))?;
+    let audio = file.tracks().iter().find(|track| track.track_type() == TrackType::Audio);
+    let dimensions = video.video().map(|video| (video.pixel_width().get() as u32, video.pixel_height().get() as u32));
+
+    let is_webm = file.ebml_header().doc_type() == "webm";
+    let playable = is_webm
+        && PLAYABLE_VIDEO_CODECS.contains(&video.codec_id())
+        && audio.is_none_or(|audio| PLAYABLE_AUDIO_CODECS.contains(&audio.codec_id()));
+
+    Ok(MediaInfo {
+        format: Some(file.ebml_header().doc_type().to_string()),
+        width: dimensions.map(|(width, _)| width),
+        height: dimensions.map(|(_, height)| height),
+        // durations are in nanoseconds, scaled by the segment's timestamp scale
+        duration_secs: file.info().duration().map(|duration| duration * file.info().timestamp_scale().get() as f64 / 1e9),
+        video_codec: Some(codec_name(video.codec_id())),
+        frame_rate: video.default_duration().map(|duration| 1e9 / duration.get() as f64),
+        audio_codec: audio.map(|audio| codec_name(audio.codec_id())),
+        sample_rate: audio.and_then(|audio| audio.audio()).map(|audio| audio.sampling_frequency() as u32),
+        channels: audio.and_then(|audio| audio.audio()).map(|audio| audio.channels().get() as u32),
+        playable: Some(playable),
+        ..MediaInfo::default()
+    })
+}
+
+/// Reads the tracks of an MP4 file, which Roblox can't play
+fn probe_mp4(path: &Path) -> Result<MediaInfo, GuavaError> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mp4 = mp4::Mp4Reader::read_header(BufReader::new(file), len).map_err(|_| malformed("invalid mp4 container"))?;
+
+    let mut tracks: Vec<&mp4::Mp4Track> = mp4.tracks().values().collect();
+    tracks.sort_by_key(|track| track.track_id());
+    let video = tracks.iter().find(|track| matches!(track.track_type(), Ok(mp4::TrackType::Video))).ok_or_else(|| malformed("no video track"))?;
+    let audio = tracks.iter().find(|track| matches!(track.track_type(), Ok(mp4::TrackType::Audio)));
+    let codec = |track: &mp4::Mp4Track| track.media_type().map_or_else(|_| String::from("unknown"), |media_type| media_type.to_string().to_lowercase());
+
+    let video_secs = video.duration().as_secs_f64();
+    Ok(MediaInfo {
+        format: Some(String::from("mp4")),
+        width: Some(video.width() as u32),
+        height: Some(video.height() as u32),
+        duration_secs: Some(mp4.duration().as_secs_f64()),
+        video_codec: Some(codec(video)),
+        frame_rate: (video_secs > 0.0).then(|| video.sample_count() as f64 / video_secs),
+        audio_codec: audio.map(|audio| codec(audio)),
+        sample_rate: audio.and_then(|audio| audio.sample_freq_index().ok()).map(|index| index.freq()),
+        channels: audio.and_then(|audio| audio.channel_config().ok()).map(|config| config as u32),
+        playable: Some(false),
+        ..MediaInfo::default()
+    })
+}
+
+/// Parses a WebM, Matroska or MP4 container for its codecs, resolution, frame rate and
+/// duration, flagging videos that aren't WebM with VP8/VP9 video and Vorbis/Opus audio,
+/// or rejecting them if configured to.
+pub fn process(path: &Path, config: &VideoConfig) -> Result<Processed, GuavaError> {
+    let mut signature = [0u8; 8];
+    let read = std::io::Read::read(&mut File::open(path)?, &mut signature)?;
+
+    let info = match &signature[..read] {
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => probe_matroska(path)?,
+        [_, _, _, _, b'f', b't', b'y', b'p'] => probe_mp4(path)?,
+        _ => return Err(malformed("unrecognised container")),
+    };
+
+    if config.reject_unplayable && info.playable == Some(false) {
+        return Err(GuavaError::Validation(String::from("videos must be WebM with VP8 or VP9 video and Vorbis or Opus audio")));
+    }
+
+    let mime_type = match info.format.as_deref() {
+        Some("webm") => "video/webm",
+        Some("mp4") => "video/mp4",
+        _ => "video/x-matroska",
+    };
+    Ok(Processed::new(mime_type, info))
+}
@@ -0,0 +1,109 @@
+/// Outcome of applying a `Range` header to a resource of known length
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    /// Serve the whole resource
+    Full,
+    /// Serve bytes `start..=end`
+    Partial { start: u64, end: u64 },
+    /// The range lies outside the resource, or more than one range was requested
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Parses a `Range` header value against a resource of `len` bytes.
+    ///
+    /// Only single `bytes` ranges are honoured. Requests for multiple ranges are rejected
+    /// as unsatisfiable rather than answered with `multipart/byteranges`, so clients can
+    /// ask for one range at a time; other units and malformed headers fall back to serving
+    /// the full resource.
+    pub fn parse(header: Option<&str>, len: u64) -> ByteRange {
+        let spec = match header.and_then(|value| value.trim().strip_prefix("bytes=")) {
+            Some(spec) if spec.contains(',') => return ByteRange::Unsatisfiable,
+            Some(spec) => spec.trim(),
+            None => return ByteRange::Full,
+        };
+
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return ByteRange::Full,
+        };
+
+        match (start.parse::<u64>(), end.parse::<u64>()) {
+            // bytes=-n, the final n bytes
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 || len == 0 {
+                    ByteRange::Unsatisfiable
+                } else {
+                    ByteRange::Partial { start: len.saturating_sub(suffix), end: len - 1 }
+                }
+            },
+            // bytes=a-
+            (Ok(start), Err(_)) if end.is_empty() => {
+                if start >= len {
+                    ByteRange::Unsatisfiable
+                } else {
+                    ByteRange::Partial { start, end: len - 1 }
+                }
+            },
+            // bytes=a-b
+            (Ok(start), Ok(end)) if start <= end => {
+                if start >= len {
+                    ByteRange::Unsatisfiable
+                } else {
+                    ByteRange::Partial { start, end: end.min(len - 1) }
+                }
+            },
+            _ => ByteRange::Full,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteRange;
+
+    #[test]
+    fn missing_or_foreign_headers_serve_everything() {
+        assert_eq!(ByteRange::parse(None, 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("items=0-10"), 100), ByteRange::Full);
+        assert_eq!(ByteRange::parse(Some("bytes=abc"), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn bounded_range() {
+        assert_eq!(ByteRange::parse(Some("bytes=10-19"), 100), ByteRange::Partial { start: 10, end: 19 });
+        // the end is clamped to the last byte
+        assert_eq!(ByteRange::parse(Some("bytes=90-200"), 100), ByteRange::Partial { start: 90, end: 99 });
+    }
+
+    #[test]
+    fn suffix_range() {
+        assert_eq!(ByteRange::parse(Some("bytes=-10"), 100), ByteRange::Partial { start: 90, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=-500"), 100), ByteRange::Partial { start: 0, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=-0"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(Some("bytes=-10"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_range() {
+        assert_eq!(ByteRange::parse(Some("bytes=40-"), 100), ByteRange::Partial { start: 40, end: 99 });
+        assert_eq!(ByteRange::parse(Some("bytes=0-"), 1), ByteRange::Partial { start: 0, end: 0 });
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        assert_eq!(ByteRange::parse(Some("bytes=100-"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(Some("bytes=100-150"), 100), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse(Some("bytes=0-"), 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn inverted_ranges_serve_everything() {
+        assert_eq!(ByteRange::parse(Some("bytes=20-10"), 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn multiple_ranges_are_unsatisfiable() {
+        assert_eq!(ByteRange::parse(Some("bytes=0-10,20-30"), 100), ByteRange::Unsatisfiable);
+    }
+}
@@ -0,0 +1,153 @@
+use std::str::FromStr;
+use futures::stream::TryStreamExt;
+use mongodb::{Collection, Database, bson::{DateTime, doc, oid::ObjectId}};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::error::GuavaError;
+
+/// Prefix of every API key, making leaked keys easy to recognise
+const KEY_PREFIX: &str = "guava_";
+
+#[derive(Clone)]
+pub struct AuthService {
+    db: Database,
+}
+
+/// Roles in increasing order of privilege; each role can do everything the ones below it can
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Reader,
+    Curator,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reader" => Ok(Role::Reader),
+            "curator" => Ok(Role::Curator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role '{}', expected reader, curator or admin", value)),
+        }
+    }
+}
+
+/// Who a request is made by, stored in the request extensions by the auth middleware
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// User id or API key id, recorded as the owner of anything created
+    pub id: String,
+    pub role: Role,
+}
+
+impl Principal {
+    /// Admins may modify anything, everyone else only what they own
+    pub fn can_modify(&self, owner: Option<&str>) -> bool {
+        self.role == Role::Admin || owner == Some(self.id.as_str())
+    }
+
+    pub fn ensure_can_modify(&self, owner: Option<&str>) -> Result<(), GuavaError> {
+        match self.can_modify(owner) {
+            true => Ok(()),
+            false => Err(GuavaError::Forbidden(String::from("only the owner or an admin can modify this"))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub name: String,
+    pub role: Role,
+    /// SHA-256 of the key; the key itself is only shown once when minted
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub key_hash: String,
+    pub created_at: DateTime,
+    pub revoked: bool,
+}
+
+/// Hashes a key or token for storage and lookup.
+///
+/// Secrets are long random strings, so a fast unsalted hash is sufficient.
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Generates a random secret with the given prefix
+pub fn generate_secret(prefix: &str) -> String {
+    let mut secret = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    format!("{}{}", prefix, hex::encode(secret))
+}
+
+impl AuthService {
+    pub fn new(db: Database) -> Self {
+        AuthService {
+            db
+        }
+    }
+
+    fn collection(&self) -> Collection<ApiKey> {
+        self.db.collection::<ApiKey>("api_keys")
+    }
+
+    /// Creates the index used to find keys by hash on every authenticated request
+    pub async fn ensure_indexes(&self) -> Result<(), GuavaError> {
+        self.db.run_command(doc! {
+            "createIndexes": "api_keys",
+            "indexes": [{ "key": { "key_hash": 1 }, "name": "api_keys_hash" }],
+        }, None).await?;
+
+        Ok(())
+    }
+
+    /// Creates a new API key, returning its record and the key itself
+    pub async fn mint(&self, name: String, role: Role) -> Result<(ApiKey, String), GuavaError> {
+        let key = generate_secret(KEY_PREFIX);
+
+        let api_key = ApiKey {
+            key_id: ObjectId::new().to_hex(),
+            name,
+            role,
+            key_hash: hash_secret(&key),
+            created_at: DateTime::now(),
+            revoked: false,
+        };
+        self.collection().insert_one(&api_key, None).await?;
+
+        Ok((api_key, key))
+    }
+
+    /// Lists all keys, without their hashes
+    pub async fn list(&self) -> Result<Vec<ApiKey>, GuavaError> {
+        let cursor = self.collection().find(None, None).await?;
+        let mut keys: Vec<ApiKey> = cursor.try_collect().await?;
+        for key in keys.iter_mut() {
+            key.key_hash.clear();
+        }
+
+        Ok(keys)
+    }
+
+    pub async fn revoke(&self, key_id: &str) -> Result<(), GuavaError> {
+        let result = self.collection().update_one(doc! { "key_id": key_id }, doc! { "$set": { "revoked": true } }, None).await?;
+
+        match result.matched_count {
+            0 => Err(GuavaError::NotFound(String::from("api key not found"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Looks up the unrevoked key matching the given key
+    pub async fn authenticate(&self, key: &str) -> Result<Option<ApiKey>, GuavaError> {
+        if !key.starts_with(KEY_PREFIX) {
+            return Ok(None);
+        }
+
+        Ok(self.collection().find_one(doc! { "key_hash": hash_secret(key), "revoked": false }, None).await?)
+    }
+}
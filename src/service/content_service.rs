@@ -1,46 +1,719 @@
-use mongodb::{Database, bson::doc};
+use std::{collections::{BTreeMap, HashMap, HashSet}, sync::{Arc, Mutex}};
+use async_std::{fs, io::{self, ReadExt}, path::Path};
+use futures::stream::TryStreamExt;
+use mongodb::{Database, bson::{Bson, DateTime, Document, doc, oid::ObjectId}, options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument}};
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use crate::error::GuavaError;
+use crate::media::MediaInfo;
+use crate::service::find_page;
+use crate::service::auth_service::Principal;
+use crate::storage::BlobStore;
+
+/// Most content ids a single hash lookup may name
+pub const MAX_HASH_LOOKUP: usize = 1000;
 
 #[derive(Clone)]
 pub struct ContentService {
-    db: Database
+    db: Database,
+    store: Arc<dyn BlobStore>,
+    verify_on_read: bool,
+    /// Hashes whose blobs have been verified since startup
+    verified: Arc<Mutex<HashSet<String>>>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum GuavaContentType {
     None = 0,
     Sound = 1,
     Video = 2,
+    Image = 3,
+    Mesh = 4,
+    Model = 5,
 }
 
-#[derive(Clone, Deserialize)]
+impl GuavaContentType {
+    /// Number of leading bytes needed by `sniff`.
+    pub const SNIFF_LEN: usize = 12;
+
+    /// MIME type content of this type is served as
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            GuavaContentType::None => "application/octet-stream",
+            GuavaContentType::Sound => "audio/ogg",
+            GuavaContentType::Video => "video/webm",
+            GuavaContentType::Image => "image/png",
+            GuavaContentType::Mesh | GuavaContentType::Model => "application/octet-stream",
+        }
+    }
+
+    /// Whether some formats of this type have no signature for `sniff` to recognise,
+    /// leaving their uploads to be checked by the type's parser
+    pub fn has_unsniffable_formats(&self) -> bool {
+        matches!(self, GuavaContentType::Image | GuavaContentType::Mesh)
+    }
+
+    /// Detect content type from the leading bytes of a file
+    pub fn sniff(header: &[u8]) -> GuavaContentType {
+        let is_mp3 = header.starts_with(b"ID3")
+            || (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0);
+        let is_wav = header.len() >= 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE";
+
+        if header.starts_with(b"version ") {
+            GuavaContentType::Mesh
+        } else if header.starts_with(b"<roblox") {
+            GuavaContentType::Model
+        } else if header.starts_with(b"\x89PNG\r\n\x1a\n") || header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            GuavaContentType::Image
+        } else if header.starts_with(b"OggS") || header.starts_with(b"fLaC") || is_wav || is_mp3 {
+            GuavaContentType::Sound
+        } else if header.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) || (header.len() >= 8 && &header[4..8] == b"ftyp") {
+            GuavaContentType::Video
+        } else {
+            GuavaContentType::None
+        }
+    }
+}
+
+/// Where content is in its processing after upload; only `ready` content is served
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentState {
+    /// Stored, waiting for a worker to process it
+    Pending,
+    Processing,
+    /// Content uploaded before processing was queued has no state and is ready
+    #[default]
+    Ready,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Content {
-    content_id: String,
-    content_type: GuavaContentType,
-    hash: String,
+    #[serde(rename = "_id", default, skip_serializing)]
+    id: Option<ObjectId>,
+    pub content_id: String,
+    pub content_type: GuavaContentType,
+    pub hash: String,
+    #[serde(default)]
+    pub state: ContentState,
+    /// Why processing failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Id of the user or API key that uploaded the content
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Display name
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Author or credit line
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub license: Option<String>,
+    /// Where the content was originally obtained
+    #[serde(default)]
+    pub source_url: Option<String>,
+    /// Size of the blob in bytes
+    #[serde(default)]
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(flatten)]
+    pub media: MediaInfo,
+    /// Other blobs of the same content, such as the original of transcoded audio
+    #[serde(default)]
+    pub variants: Vec<ContentVariant>,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime>,
+}
+
+/// Alternative blob of a piece of content, downloadable by name
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ContentVariant {
+    pub name: String,
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+}
+
+impl Content {
+    /// Hashes of every blob of this content
+    pub fn hashes(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.hash).chain(self.variants.iter().map(|variant| &variant.hash))
+    }
+
+    /// Fails unless the content has been processed and can be served
+    pub fn ensure_ready(&self) -> Result<(), GuavaError> {
+        match self.state {
+            ContentState::Ready => Ok(()),
+            ContentState::Pending | ContentState::Processing => Err(GuavaError::Conflict(String::from("content is still being processed"))),
+            ContentState::Failed => Err(GuavaError::Conflict(String::from("content failed processing"))),
+        }
+    }
+
+    /// Hash and MIME type of the named variant, or of the main blob if none is named
+    pub fn variant(&self, name: Option<&str>) -> Result<(&str, &str), GuavaError> {
+        match name {
+            None => Ok((&self.hash, self.mime_type.as_deref().unwrap_or(self.content_type.mime_type()))),
+            Some(name) => self.variants.iter()
+                .find(|variant| variant.name == name)
+                .map(|variant| (variant.hash.as_str(), variant.mime_type.as_str()))
+                .ok_or_else(|| GuavaError::NotFound(String::from("variant not found"))),
+        }
+    }
+}
+
+/// Blob stored from an upload or produced by processing it
+pub struct StoredUpload {
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+    pub media: MediaInfo,
+    pub variants: Vec<ContentVariant>,
+}
+
+/// What a client needs to fetch a piece of content
+#[derive(Clone, Debug, Serialize)]
+pub struct ContentHash {
+    pub hash: String,
+    #[serde(rename = "type")]
+    pub content_type: GuavaContentType,
+    pub size: u64,
+}
+
+/// Outcome of looking up the hashes of many pieces of content at once
+#[derive(Clone, Debug, Serialize)]
+pub struct HashLookup {
+    pub hashes: BTreeMap<String, ContentHash>,
+    /// Ids of content that doesn't exist
+    pub missing: Vec<String>,
+    /// Ids of content that is still being processed or failed processing
+    pub not_ready: Vec<String>,
+    /// Ids of content only in private playlists the caller can't read
+    pub forbidden: Vec<String>,
+}
+
+/// Descriptive metadata of content, given on upload and editable afterwards.
+///
+/// Absent fields are left unchanged; empty strings clear a field.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentMetadata {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub tags: Option<Vec<String>>,
+    pub author: Option<String>,
+    pub license: Option<String>,
+    pub source_url: Option<String>,
+}
+
+impl ContentMetadata {
+    const MAX_TEXT_LEN: usize = 256;
+    const MAX_DESCRIPTION_LEN: usize = 4096;
+    const MAX_TAGS: usize = 32;
+    const MAX_TAG_LEN: usize = 32;
+
+    /// Checks field lengths and that the source is an http(s) URL
+    pub fn validate(&self) -> Result<(), GuavaError> {
+        let too_long = |field: &str, max: usize| GuavaError::Validation(format!("{} must be at most {} characters", field, max));
+
+        for (field, value) in [("name", &self.name), ("author", &self.author), ("license", &self.license), ("source_url", &self.source_url)] {
+            if value.as_ref().is_some_and(|value| value.chars().count() > Self::MAX_TEXT_LEN) {
+                return Err(too_long(field, Self::MAX_TEXT_LEN));
+            }
+        }
+        if self.description.as_ref().is_some_and(|description| description.chars().count() > Self::MAX_DESCRIPTION_LEN) {
+            return Err(too_long("description", Self::MAX_DESCRIPTION_LEN));
+        }
+
+        if let Some(tags) = &self.tags {
+            if tags.len() > Self::MAX_TAGS {
+                return Err(GuavaError::Validation(format!("at most {} tags are allowed", Self::MAX_TAGS)));
+            }
+            if tags.iter().any(|tag| tag.chars().count() > Self::MAX_TAG_LEN) {
+                return Err(too_long("tags", Self::MAX_TAG_LEN));
+            }
+        }
+
+        let source_url = self.source_url.as_deref().map(str::trim).unwrap_or_default();
+        if !source_url.is_empty() && !source_url.starts_with("https://") && !source_url.starts_with("http://") {
+            return Err(GuavaError::Validation(String::from("source_url must be an http or https URL")));
+        }
+
+        Ok(())
+    }
+
+    /// Fields to `$set`, with empty strings cleared
+    fn changes(self) -> Document {
+        let text = |value: String| match value.trim() {
+            "" => Bson::Null,
+            value => Bson::String(value.to_string()),
+        };
+
+        let mut changes = Document::new();
+        for (field, value) in [("name", self.name), ("description", self.description), ("author", self.author), ("license", self.license), ("source_url", self.source_url)] {
+            if let Some(value) = value {
+                changes.insert(field, text(value));
+            }
+        }
+        if let Some(tags) = self.tags {
+            changes.insert("tags", normalize_tags(tags.iter().map(String::as_str)));
+        }
+
+        changes
+    }
+}
+
+/// Filters and page of a content listing
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ContentListQuery {
+    #[serde(rename = "type")]
+    pub content_type: Option<GuavaContentType>,
+    pub state: Option<ContentState>,
+    /// Full-text search over names and tags
+    pub q: Option<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// RFC 3339 timestamps bounding the upload time
+    pub created_after: Option<String>,
+    pub created_before: Option<String>,
+    /// Maximum content to return, at most `MAX_PAGE_LIMIT`
+    pub limit: Option<i64>,
+    /// Cursor returned as `next` by the previous page
+    pub after: Option<String>,
+}
+
+/// One page of a content listing, newest first; `next` is absent on the last page
+#[derive(Clone, Debug, Serialize)]
+pub struct ContentPage {
+    pub content: Vec<Content>,
+    pub next: Option<String>,
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime, GuavaError> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| DateTime::from_millis(timestamp.timestamp_millis()))
+        .map_err(|_| GuavaError::BadRequest(format!("invalid timestamp '{}', expected RFC 3339", value)))
+}
+
+/// Trims and lowercases tags, dropping empty ones and duplicates
+fn normalize_tags<'a>(tags: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut normalized: Vec<String> = vec![];
+    for tag in tags.map(|tag| tag.trim().to_lowercase()) {
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
 }
 
 impl ContentService {
-    pub fn new(db: Database) -> Self {
+    pub fn new(db: Database, store: Arc<dyn BlobStore>, verify_on_read: bool) -> Self {
         ContentService {
-            db
+            db,
+            store,
+            verify_on_read,
+            verified: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
-    pub async fn get_hash_from_id(&self, id: String) -> Result<String, ()> {
+    /// Blob store holding the data of all content
+    pub fn store(&self) -> &dyn BlobStore {
+        self.store.as_ref()
+    }
+
+    pub async fn get_content_from_id(&self, id: String) -> Result<Content, GuavaError> {
         let collection = self.db.collection::<Content>("content");
 
         match collection.find_one(doc! {
             "content_id": id
-        }, None).await {
-            Ok(content) => {
-                match content {
-                    Some(content_unwrapped) => Ok(content_unwrapped.hash),
-                    None => Err(()), 
+        }, None).await? {
+            Some(content) => Ok(content),
+            None => Err(GuavaError::NotFound(String::from("content not found"))),
+        }
+    }
+
+    pub async fn get_all_contents(&self) -> Result<Vec<Content>, GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+
+        let cursor = collection.find(None, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Looks up all content with the given ids in a single query
+    pub async fn get_contents_from_ids(&self, ids: Vec<String>) -> Result<Vec<Content>, GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+
+        let cursor = collection.find(doc! {
+            "content_id": { "$in": ids }
+        }, None).await?;
+
+        Ok(cursor.try_collect().await?)
+    }
+
+    /// Checks and deduplicates the ids of a hash lookup
+    pub fn hash_lookup_ids(ids: Vec<String>) -> Result<Vec<String>, GuavaError> {
+        if ids.len() > MAX_HASH_LOOKUP {
+            return Err(GuavaError::BadRequest(format!("at most {} hashes can be looked up at once", MAX_HASH_LOOKUP)));
+        }
+
+        let mut seen = HashSet::new();
+        Ok(ids.into_iter().filter(|id| seen.insert(id.clone())).collect())
+    }
+
+    /// Looks up the hashes of ready content in a single query, reporting unknown ids separately.
+    ///
+    /// `ids` are as returned by `hash_lookup_ids`. Content in `hidden` is reported as
+    /// forbidden unless `viewer` owns it.
+    pub async fn lookup_hashes(&self, ids: Vec<String>, hidden: &HashSet<String>, viewer: Option<&Principal>) -> Result<HashLookup, GuavaError> {
+
+        let mut contents: HashMap<String, Content> = self.get_contents_from_ids(ids.clone()).await?
+            .into_iter()
+            .map(|content| (content.content_id.clone(), content))
+            .collect();
+
+        let mut lookup = HashLookup {
+            hashes: BTreeMap::new(),
+            missing: vec![],
+            not_ready: vec![],
+            forbidden: vec![],
+        };
+        for id in ids {
+            let content = match contents.remove(&id) {
+                Some(content) => content,
+                None => {
+                    lookup.missing.push(id);
+                    continue;
                 }
-            },
-            Err(_) => Err(()),
+            };
+            if hidden.contains(&id) && !viewer.is_some_and(|viewer| viewer.can_modify(content.owner.as_deref())) {
+                lookup.forbidden.push(id);
+                continue;
+            }
+            if content.ensure_ready().is_err() {
+                lookup.not_ready.push(id);
+                continue;
+            }
+
+            let size = match content.size {
+                Some(size) => size,
+                // content uploaded before sizes were recorded
+                None => self.store.size(&content.hash).await?,
+            };
+            lookup.hashes.insert(id, ContentHash {
+                hash: content.hash,
+                content_type: content.content_type,
+                size,
+            });
+        }
+
+        Ok(lookup)
+    }
+
+    /// Creates the text index backing content search
+    pub async fn ensure_indexes(&self) -> Result<(), GuavaError> {
+        self.db.run_command(doc! {
+            "createIndexes": "content",
+            "indexes": [{ "key": { "name": "text", "tags": "text" }, "name": "content_text" }],
+        }, None).await?;
+
+        Ok(())
+    }
+
+    /// Lists a page of content matching the query, newest first, leaving out `hidden` content
+    /// unless it is owned by `viewer_id`
+    pub async fn list_contents(&self, query: ContentListQuery, hidden: Vec<String>, viewer_id: &str) -> Result<ContentPage, GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+
+        let mut filter = Document::new();
+        if !hidden.is_empty() {
+            filter.insert("$or", vec![doc! { "owner": viewer_id }, doc! { "content_id": { "$nin": hidden } }]);
+        }
+        if let Some(content_type) = query.content_type {
+            filter.insert("content_type", mongodb::bson::to_bson(&content_type)?);
+        }
+        match query.state {
+            // content from before processing was queued has no state
+            Some(ContentState::Ready) => { filter.insert("state", doc! { "$in": ["ready", Bson::Null] }); },
+            Some(state) => { filter.insert("state", mongodb::bson::to_bson(&state)?); },
+            None => {},
+        }
+        if let Some(q) = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+            filter.insert("$text", doc! { "$search": q });
+        }
+
+        let mut size = Document::new();
+        if let Some(min_size) = query.min_size {
+            size.insert("$gte", min_size as i64);
+        }
+        if let Some(max_size) = query.max_size {
+            size.insert("$lte", max_size as i64);
+        }
+        if !size.is_empty() {
+            filter.insert("size", size);
+        }
+
+        let mut created_at = Document::new();
+        if let Some(created_after) = &query.created_after {
+            created_at.insert("$gte", parse_timestamp(created_after)?);
+        }
+        if let Some(created_before) = &query.created_before {
+            created_at.insert("$lt", parse_timestamp(created_before)?);
+        }
+        if !created_at.is_empty() {
+            filter.insert("created_at", created_at);
+        }
+
+        if let Some(after) = &query.after {
+            let after = ObjectId::parse_str(after).map_err(|_| GuavaError::BadRequest(String::from("invalid cursor")))?;
+            filter.insert("_id", doc! { "$lt": after });
+        }
+
+        let options = FindOptions::builder().sort(doc! { "_id": -1 }).build();
+        let (content, next) = find_page(&collection, filter, options, query.limit,
+            |last| last.id.map(|id| id.to_hex())).await?;
+
+        Ok(ContentPage { content, next })
+    }
+
+    /// Registers a stored upload as new content awaiting processing, returning its content id
+    pub async fn create_content(&self, content_type: GuavaContentType, upload: StoredUpload, metadata: ContentMetadata, owner: String) -> Result<String, GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+        let content_id = ObjectId::new().to_hex();
+
+        let mut content = doc! {
+            "content_id": &content_id,
+            "content_type": mongodb::bson::to_bson(&content_type)?,
+            "hash": upload.hash,
+            "state": mongodb::bson::to_bson(&ContentState::Pending)?,
+            "owner": owner,
+            "size": upload.size as i64,
+            "mime_type": upload.mime_type,
+            "created_at": DateTime::now(),
+            "updated_at": DateTime::now(),
+        };
+        content.extend(mongodb::bson::to_document(&upload.media)?);
+        content.insert("variants", mongodb::bson::to_bson(&upload.variants)?);
+        content.extend(metadata.changes());
+
+        collection.clone_with_type::<Document>().insert_one(content, None).await?;
+        Ok(content_id)
+    }
+
+    /// Deletes a blob no content refers to, such as an upload whose content couldn't be created
+    pub async fn discard_blob(&self, hash: &str) -> Result<(), GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+        let filter = doc! { "$or": [{ "hash": hash }, { "variants.hash": hash }] };
+
+        // the same file may have been uploaded as other content
+        if collection.count_documents(filter, None).await? > 0 {
+            return Ok(());
+        }
+        self.store.delete(hash).await
+    }
+
+    /// Moves content to another processing state, recording why if it failed
+    pub async fn set_state(&self, id: &str, state: ContentState, error: Option<String>) -> Result<(), GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+
+        let update = match error {
+            Some(error) => doc! { "$set": { "state": mongodb::bson::to_bson(&state)?, "error": error, "updated_at": DateTime::now() } },
+            None => doc! { "$set": { "state": mongodb::bson::to_bson(&state)?, "updated_at": DateTime::now() }, "$unset": { "error": "" } },
+        };
+        let result = collection.update_one(doc! { "content_id": id }, update, None).await?;
+
+        match result.matched_count {
+            0 => Err(GuavaError::NotFound(String::from("content not found"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records the outcome of processing content, making it ready to be served
+    pub async fn finish_processing(&self, id: &str, processed: StoredUpload) -> Result<(), GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+
+        let mut changes = doc! {
+            "hash": processed.hash,
+            "size": processed.size as i64,
+            "mime_type": processed.mime_type,
+            "variants": mongodb::bson::to_bson(&processed.variants)?,
+            "state": mongodb::bson::to_bson(&ContentState::Ready)?,
+            "updated_at": DateTime::now(),
+        };
+        changes.extend(mongodb::bson::to_document(&processed.media)?);
+
+        let result = collection.update_one(doc! { "content_id": id }, doc! { "$set": changes, "$unset": { "error": "" } }, None).await?;
+        match result.matched_count {
+            0 => Err(GuavaError::NotFound(String::from("content not found"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copies a stored blob to a local file, for processing that can't work on a stream
+    pub async fn copy_blob(&self, hash: &str, path: &Path) -> Result<(), GuavaError> {
+        let mut reader = self.store.stream(hash).await?;
+        let mut file = fs::File::create(path).await?;
+        let copied = io::copy(&mut reader, &mut file).await;
+        drop(file);
+
+        if let Err(e) = copied {
+            fs::remove_file(path).await.ok();
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Gets content with the size and MIME type of content uploaded before they were recorded filled in
+    pub async fn get_details(&self, id: String) -> Result<Content, GuavaError> {
+        let mut content = self.get_content_from_id(id).await?;
+
+        if content.size.is_none() {
+            content.size = Some(self.store.size(&content.hash).await?);
+        }
+        if content.mime_type.is_none() {
+            content.mime_type = Some(content.content_type.mime_type().to_string());
+        }
+
+        Ok(content)
+    }
+
+    /// Adds a variant to content, replacing any variant of the same name
+    pub async fn set_variant(&self, id: &str, variant: ContentVariant) -> Result<(), GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+        let filter = doc! { "content_id": id };
+
+        collection.update_one(filter.clone(), doc! { "$pull": { "variants": { "name": &variant.name } } }, None).await?;
+        let result = collection.update_one(filter, doc! { "$push": { "variants": mongodb::bson::to_bson(&variant)? } }, None).await?;
+
+        match result.matched_count {
+            0 => Err(GuavaError::NotFound(String::from("content not found"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies metadata changes, returning the updated content
+    pub async fn update_metadata(&self, id: &str, metadata: ContentMetadata) -> Result<Content, GuavaError> {
+        let collection = self.db.collection::<Content>("content");
+        metadata.validate()?;
+
+        let mut changes = metadata.changes();
+        if changes.is_empty() {
+            return Err(GuavaError::BadRequest(String::from("nothing to update")));
+        }
+        changes.insert("updated_at", DateTime::now());
+
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+        match collection.find_one_and_update(doc! { "content_id": id }, doc! { "$set": changes }, options).await? {
+            Some(content) => Ok(content),
+            None => Err(GuavaError::NotFound(String::from("content not found"))),
+        }
+    }
+
+    /// Rehashes a stored blob, returning whether it matches its hash.
+    ///
+    /// Only SHA-256 hashes can be checked; any other hash is reported as unverifiable.
+    pub async fn verify_blob(&self, hash: &str) -> Result<Option<bool>, GuavaError> {
+        if hash.len() != 64 {
+            return Ok(None);
+        }
+
+        let mut reader = self.store.stream(hash).await?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+
+        let valid = hex::encode(hasher.finalize()) == hash;
+        if valid {
+            self.verified.lock().unwrap().insert(hash.to_string());
         }
+
+        Ok(Some(valid))
+    }
+
+    /// Verifies a blob the first time it is read, if verification on read is enabled
+    pub async fn check_blob_before_read(&self, hash: &str) -> Result<(), GuavaError> {
+        if !self.verify_on_read || self.verified.lock().unwrap().contains(hash) {
+            return Ok(());
+        }
+
+        match self.verify_blob(hash).await? {
+            Some(false) => Err(GuavaError::Internal(format!("blob {} does not match its hash", hash))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuavaContentType;
+
+    fn sniff(header: &[u8]) -> GuavaContentType {
+        GuavaContentType::sniff(header)
+    }
+
+    #[test]
+    fn sniffs_meshes_and_models() {
+        assert_eq!(sniff(b"version 4.00\n"), GuavaContentType::Mesh);
+        assert_eq!(sniff(b"<roblox!\x89\xff\r\n\x1a\n"), GuavaContentType::Model);
+        assert_eq!(sniff(b"<roblox xmlns"), GuavaContentType::Model);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sniffs_images() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0d"), GuavaContentType::Image);
+        assert_eq!(sniff(&[0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F', 0, 1]), GuavaContentType::Image);
+    }
+
+    #[test]
+    fn sniffs_sounds() {
+        assert_eq!(sniff(b"OggS\0\x02\0\0\0\0\0\0"), GuavaContentType::Sound);
+        assert_eq!(sniff(b"fLaC\0\0\0\x22"), GuavaContentType::Sound);
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVE"), GuavaContentType::Sound);
+        assert_eq!(sniff(b"ID3\x04\0\0\0\0\0\0"), GuavaContentType::Sound);
+    }
+
+    #[test]
+    fn sniffs_mp3_frame_sync() {
+        // MPEG-1 layer III and MPEG-2 layer III frame headers without an ID3 tag
+        assert_eq!(sniff(&[0xFF, 0xFB, 0x90, 0x64]), GuavaContentType::Sound);
+        assert_eq!(sniff(&[0xFF, 0xF3, 0x48, 0xC4]), GuavaContentType::Sound);
+        // eleven set bits are needed, and a lone 0xFF is not enough
+        assert_eq!(sniff(&[0xFF, 0xC0, 0x00, 0x00]), GuavaContentType::None);
+        assert_eq!(sniff(&[0xFF]), GuavaContentType::None);
+    }
+
+    #[test]
+    fn sniffs_videos() {
+        assert_eq!(sniff(&[0x1A, 0x45, 0xDF, 0xA3, 0x9F, 0x42, 0x86, 0x81]), GuavaContentType::Video);
+        assert_eq!(sniff(b"\0\0\0\x20ftypisom"), GuavaContentType::Video);
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), GuavaContentType::Video);
+        // `ftyp` only counts as the box type after the size
+        assert_eq!(sniff(b"ftyp\0\0\0\0"), GuavaContentType::None);
+        assert_eq!(sniff(b"\0\0\0\x20fty"), GuavaContentType::None);
+    }
+
+    #[test]
+    fn unknown_or_truncated_headers_are_none() {
+        assert_eq!(sniff(b""), GuavaContentType::None);
+        assert_eq!(sniff(b"hello world!"), GuavaContentType::None);
+        // RIFF without the WAVE form, e.g. WebP images
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBP"), GuavaContentType::None);
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WA"), GuavaContentType::None);
+        assert_eq!(sniff(b"versio"), GuavaContentType::None);
+    }
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+use async_std::{fs, path::{Path, PathBuf}};
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use crate::config::Config;
+use crate::error::GuavaError;
+use crate::media::{self, MediaInfo};
+use crate::service::content_service::{Content, ContentService, ContentState, ContentVariant, StoredUpload};
+use crate::service::job_service::{Job, JobKind, JobRunner, JobService};
+use crate::service::scrub_service::ScrubService;
+use crate::service::thumbnail_service::ThumbnailService;
+
+/// Runs the background jobs of uploaded content: processing it, then generating its preview
+#[derive(Clone)]
+pub struct IngestService {
+    config: Arc<Config>,
+    content_service: ContentService,
+    job_service: JobService,
+    thumbnail_service: ThumbnailService,
+    scrub_service: ScrubService,
+}
+
+impl IngestService {
+    pub fn new(config: Arc<Config>, content_service: ContentService, job_service: JobService, thumbnail_service: ThumbnailService, scrub_service: ScrubService) -> Self {
+        IngestService {
+            config,
+            content_service,
+            job_service,
+            thumbnail_service,
+            scrub_service,
+        }
+    }
+
+    /// Queues a failed job to run again, putting content it failed to process back in line
+    pub async fn retry(&self, job_id: &str) -> Result<Job, GuavaError> {
+        let job = self.job_service.retry(job_id).await?;
+
+        if let (JobKind::Process, Some(content_id)) = (job.kind, &job.content_id) {
+            self.content_service.set_state(content_id, ContentState::Pending, None).await?;
+        }
+        Ok(job)
+    }
+
+    /// Validates and normalises stored content according to its type, then marks it ready
+    async fn process(&self, content_id: &str) -> Result<(), GuavaError> {
+        let content = self.content_service.get_content_from_id(content_id.to_string()).await?;
+        if content.state == ContentState::Ready {
+            // processed by an earlier attempt that stopped before recording its outcome
+            return Ok(());
+        }
+        self.content_service.set_state(content_id, ContentState::Processing, None).await?;
+
+        let staging_dir = PathBuf::from(&self.config.storage.staging_dir);
+        fs::create_dir_all(&staging_dir).await?;
+        let staged = staging_dir.join(format!("process-{}", ObjectId::new().to_hex()));
+        self.content_service.copy_blob(&content.hash, &staged).await?;
+
+        let processed = self.process_staged(&staged, &content).await;
+        fs::remove_file(&staged).await.ok();
+        self.content_service.finish_processing(content_id, processed?).await?;
+
+        if self.config.thumbnails.enabled {
+            self.job_service.enqueue(JobKind::Thumbnail, Some(content_id.to_string())).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs the media processing of a staged copy of content, storing anything it was converted into
+    async fn process_staged(&self, staged: &Path, content: &Content) -> Result<StoredUpload, GuavaError> {
+        let size = match content.size {
+            Some(size) => size,
+            None => self.content_service.store().size(&content.hash).await?,
+        };
+        let mut upload = StoredUpload {
+            hash: content.hash.clone(),
+            size,
+            mime_type: content.content_type.mime_type().to_string(),
+            media: MediaInfo::default(),
+            variants: vec![],
+        };
+
+        let processed = match media::process(staged, content.content_type, &self.config).await? {
+            Some(processed) => processed,
+            None => return Ok(upload),
+        };
+        upload.mime_type = processed.mime_type.to_string();
+        upload.media = processed.info;
+
+        if let Some(converted) = processed.converted {
+            let stored = async {
+                let (hash, size) = media::hash_file(&converted).await?;
+                self.content_service.store().put(&hash, &converted).await?;
+                Ok::<_, GuavaError>((hash, size))
+            }.await;
+            // the store may already have moved the converted file into place
+            fs::remove_file(&converted).await.ok();
+            let (hash, size) = stored?;
+
+            // the upload itself is already stored under its hash
+            let original_hash = std::mem::replace(&mut upload.hash, hash);
+            let original_size = std::mem::replace(&mut upload.size, size);
+            if let Some(mime_type) = processed.original_mime_type {
+                upload.variants.push(ContentVariant {
+                    name: String::from("original"),
+                    hash: original_hash,
+                    size: original_size,
+                    mime_type: mime_type.to_string(),
+                });
+            }
+        }
+
+        Ok(upload)
+    }
+}
+
+#[async_trait]
+impl JobRunner for IngestService {
+    async fn run(&self, job: &Job) -> Result<(), GuavaError> {
+        let content_id = || job.content_id.as_deref()
+            .ok_or_else(|| GuavaError::BadRequest(format!("{:?} job has no content", job.kind)));
+
+        match job.kind {
+            JobKind::Process => self.process(content_id()?).await,
+            JobKind::Thumbnail => self.thumbnail_service.generate(content_id()?).await,
+            JobKind::Scrub => {
+                let report = self.scrub_service.run().await?;
+                tide::log::info!("Scrub finished: {} checked, {} corrupt, {} missing, {} orphans",
+                    report.checked, report.corrupt.len(), report.missing.len(), report.orphans.len());
+                Ok(())
+            },
+        }
+    }
+
+    async fn give_up(&self, job: &Job, error: &GuavaError) -> Result<(), GuavaError> {
+        match (job.kind, &job.content_id) {
+            (JobKind::Process, Some(content_id)) => self.content_service.set_state(content_id, ContentState::Failed, Some(error.message())).await,
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use std::{sync::Arc, time::Duration};
+use async_std::{channel::{self, Receiver, Sender}, future, task};
+use async_trait::async_trait;
+use futures::future::Either;
+use mongodb::{Database, bson::{DateTime, Document, doc, oid::ObjectId}, options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument}};
+use serde::{Serialize, Deserialize};
+use crate::config::JobConfig;
+use crate::error::GuavaError;
+use crate::service::{find_page, is_duplicate_key};
+
+/// How long idle workers wait before checking for due jobs queued elsewhere or retried
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Longest delay between retries of a job
+const MAX_BACKOFF_SECS: u64 = 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    /// Validate and normalise uploaded content
+    Process,
+    /// Generate the preview of content
+    Thumbnail,
+    /// Check every stored blob against its hash
+    Scrub,
+}
+
+impl JobKind {
+    /// Key shared by jobs of this kind that must not be queued or run more than once at a time
+    fn unique_key(&self) -> Option<&'static str> {
+        match self {
+            JobKind::Scrub => Some("scrub"),
+            JobKind::Process | JobKind::Thumbnail => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Job {
+    #[serde(rename = "_id", default, skip_serializing)]
+    id: Option<ObjectId>,
+    pub job_id: String,
+    pub kind: JobKind,
+    /// Content the job works on, if any
+    pub content_id: Option<String>,
+    pub status: JobStatus,
+    /// Number of times the job has been started
+    pub attempts: u32,
+    pub max_attempts: u32,
+    /// When the job is next due to run
+    pub run_at: DateTime,
+    /// When a running job is presumed abandoned by its worker and run again
+    pub locked_until: Option<DateTime>,
+    /// Client-safe message of the latest failure; the full error is only logged
+    pub last_error: Option<String>,
+    /// Set from `JobKind::unique_key` while the job is queued or running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unique_key: Option<String>,
+    pub created_at: DateTime,
+    pub updated_at: DateTime,
+    pub finished_at: Option<DateTime>,
+}
+
+/// Filters and page of a job listing
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct JobListQuery {
+    pub status: Option<JobStatus>,
+    pub kind: Option<JobKind>,
+    pub content_id: Option<String>,
+    /// Maximum jobs to return, at most `MAX_PAGE_LIMIT`
+    pub limit: Option<i64>,
+    /// Cursor returned as `next` by the previous page
+    pub after: Option<String>,
+}
+
+/// One page of a job listing, newest first; `next` is absent on the last page
+#[derive(Clone, Debug, Serialize)]
+pub struct JobPage {
+    pub jobs: Vec<Job>,
+    pub next: Option<String>,
+}
+
+/// Carries out claimed jobs
+#[async_trait]
+pub trait JobRunner: Send + Sync {
+    async fn run(&self, job: &Job) -> Result<(), GuavaError>;
+
+    /// Called once a job has failed for the last time
+    async fn give_up(&self, job: &Job, error: &GuavaError) -> Result<(), GuavaError>;
+}
+
+/// Whether a job failing with this error could succeed if run again
+fn is_retryable(error: &GuavaError) -> bool {
+    !matches!(error, GuavaError::NotFound(_) | GuavaError::Conflict(_) | GuavaError::BadRequest(_) | GuavaError::Validation(_))
+}
+
+/// Whether a write failed because it would duplicate a unique index key
+fn seconds_from_now(secs: u64) -> DateTime {
+    DateTime::from_millis(DateTime::now().timestamp_millis() + secs as i64 * 1000)
+}
+
+/// Durable queue of background work, persisted in the `jobs` collection so it survives restarts
+#[derive(Clone)]
+pub struct JobService {
+    db: Database,
+    config: JobConfig,
+    /// Wakes idle workers of this server when a job is queued
+    wake_sender: Sender<()>,
+    wake_receiver: Receiver<()>,
+}
+
+impl JobService {
+    pub fn new(db: Database, config: JobConfig) -> Self {
+        // one pending wake-up is enough, workers claim every due job before waiting again
+        let (wake_sender, wake_receiver) = channel::bounded(1);
+
+        JobService {
+            db,
+            config,
+            wake_sender,
+            wake_receiver,
+        }
+    }
+
+    fn collection(&self) -> mongodb::Collection<Job> {
+        self.db.collection::<Job>("jobs")
+    }
+
+    /// Creates the indexes used to find due jobs and keep unique jobs from being queued twice
+    pub async fn ensure_indexes(&self) -> Result<(), GuavaError> {
+        self.db.run_command(doc! {
+            "createIndexes": "jobs",
+            "indexes": [
+                { "key": { "status": 1, "run_at": 1 }, "name": "jobs_due" },
+                { "key": { "content_id": 1 }, "name": "jobs_content" },
+                { "key": { "unique_key": 1 }, "name": "jobs_unique", "unique": true, "sparse": true },
+            ],
+        }, None).await?;
+
+        Ok(())
+    }
+
+    /// Queues a job to run as soon as a worker is free.
+    ///
+    /// Fails with `Conflict` if the kind only runs once at a time and a job of it is already
+    /// queued or running.
+    pub async fn enqueue(&self, kind: JobKind, content_id: Option<String>) -> Result<Job, GuavaError> {
+        let now = DateTime::now();
+        let id = ObjectId::new();
+
+        let job = Job {
+            id: Some(id),
+            job_id: id.to_hex(),
+            kind,
+            content_id,
+            status: JobStatus::Queued,
+            attempts: 0,
+            max_attempts: self.config.max_attempts,
+            run_at: now,
+            locked_until: None,
+            last_error: None,
+            unique_key: kind.unique_key().map(String::from),
+            created_at: now,
+            updated_at: now,
+            finished_at: None,
+        };
+        let mut document = mongodb::bson::to_document(&job)?;
+        document.insert("_id", id);
+        match self.collection().clone_with_type::<Document>().insert_one(document, None).await {
+            Ok(_) => {},
+            Err(e) if is_duplicate_key(&e) => return Err(GuavaError::Conflict(format!("a {:?} job is already queued or running", kind))),
+            Err(e) => return Err(e.into()),
+        }
+
+        self.wake_sender.try_send(()).ok();
+        Ok(job)
+    }
+
+    pub async fn get(&self, job_id: &str) -> Result<Job, GuavaError> {
+        match self.collection().find_one(doc! { "job_id": job_id }, None).await? {
+            Some(job) => Ok(job),
+            None => Err(GuavaError::NotFound(String::from("job not found"))),
+        }
+    }
+
+    /// Lists a page of jobs matching the query, newest first
+    pub async fn list(&self, query: JobListQuery) -> Result<JobPage, GuavaError> {
+        let mut filter = Document::new();
+        if let Some(status) = query.status {
+            filter.insert("status", mongodb::bson::to_bson(&status)?);
+        }
+        if let Some(kind) = query.kind {
+            filter.insert("kind", mongodb::bson::to_bson(&kind)?);
+        }
+        if let Some(content_id) = query.content_id {
+            filter.insert("content_id", content_id);
+        }
+        if let Some(after) = &query.after {
+            let after = ObjectId::parse_str(after).map_err(|_| GuavaError::BadRequest(String::from("invalid cursor")))?;
+            filter.insert("_id", doc! { "$lt": after });
+        }
+
+        let options = FindOptions::builder().sort(doc! { "_id": -1 }).build();
+        let (jobs, next) = find_page(&self.collection(), filter, options, query.limit,
+            |last| last.id.map(|id| id.to_hex())).await?;
+
+        Ok(JobPage { jobs, next })
+    }
+
+    /// Queues a failed job to run again with a fresh set of attempts
+    pub async fn retry(&self, job_id: &str) -> Result<Job, GuavaError> {
+        let now = DateTime::now();
+        let options = FindOneAndUpdateOptions::builder().return_document(ReturnDocument::After).build();
+
+        let failed = self.get(job_id).await?;
+        if failed.status != JobStatus::Failed {
+            return Err(GuavaError::Conflict(String::from("only failed jobs can be retried")));
+        }
+
+        let mut changes = doc! { "status": "queued", "attempts": 0, "run_at": now, "updated_at": now };
+        if let Some(unique_key) = failed.kind.unique_key() {
+            changes.insert("unique_key", unique_key);
+        }
+        let job = match self.collection().find_one_and_update(
+            doc! { "job_id": job_id, "status": "failed" },
+            doc! {
+                "$set": changes,
+                "$unset": { "finished_at": "", "locked_until": "" },
+            },
+            options,
+        ).await {
+            Ok(job) => job,
+            Err(e) if is_duplicate_key(&e) => return Err(GuavaError::Conflict(format!("a {:?} job is already queued or running", failed.kind))),
+            Err(e) => return Err(e.into()),
+        };
+
+        match job {
+            Some(job) => {
+                self.wake_sender.try_send(()).ok();
+                Ok(job)
+            },
+            // retried by someone else in the meantime
+            None => Err(GuavaError::Conflict(String::from("only failed jobs can be retried"))),
+        }
+    }
+
+    /// Takes the next due job, including jobs whose worker stopped before finishing them
+    async fn claim(&self) -> Result<Option<Job>, GuavaError> {
+        let now = DateTime::now();
+        let filter = doc! {
+            "$or": [
+                { "status": "queued", "run_at": { "$lte": now } },
+                { "status": "running", "locked_until": { "$lt": now } },
+            ],
+        };
+        let update = doc! {
+            "$set": { "status": "running", "locked_until": seconds_from_now(self.config.lease_secs), "updated_at": now },
+            "$inc": { "attempts": 1 },
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! { "run_at": 1 })
+            .return_document(ReturnDocument::After)
+            .build();
+
+        Ok(self.collection().find_one_and_update(filter, update, options).await?)
+    }
+
+    /// Filter matching the job only while it is still held by the worker that claimed it
+    fn claimed(job: &Job) -> Document {
+        doc! { "job_id": &job.job_id, "status": "running", "attempts": job.attempts }
+    }
+
+    /// Extends the lease of a running job, returning whether it is still held
+    async fn renew(&self, job: &Job) -> Result<bool, GuavaError> {
+        let result = self.collection().update_one(Self::claimed(job), doc! {
+            "$set": { "locked_until": seconds_from_now(self.config.lease_secs) },
+        }, None).await?;
+
+        Ok(result.matched_count > 0)
+    }
+
+    /// Renews the lease of a job for as long as it runs, so no other worker claims it
+    async fn heartbeat(&self, job: &Job) {
+        let interval = Duration::from_secs((self.config.lease_secs / 3).max(1));
+        loop {
+            task::sleep(interval).await;
+            match self.renew(job).await {
+                Ok(true) => {},
+                Ok(false) => tide::log::warn!("Job {} ({:?}) is no longer held by its worker", job.job_id, job.kind),
+                Err(e) => tide::log::warn!("Failed to renew lease of job {}: {}", job.job_id, e),
+            }
+        }
+    }
+
+    async fn succeed(&self, job: &Job) -> Result<(), GuavaError> {
+        let now = DateTime::now();
+        self.collection().update_one(Self::claimed(job), doc! {
+            "$set": { "status": "succeeded", "finished_at": now, "updated_at": now },
+            "$unset": { "locked_until": "", "last_error": "", "unique_key": "" },
+        }, None).await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt, returning whether the job will be retried
+    async fn fail(&self, job: &Job, error: &GuavaError) -> Result<bool, GuavaError> {
+        let now = DateTime::now();
+        let retry = is_retryable(error) && job.attempts < job.max_attempts;
+
+        let update = match retry {
+            true => {
+                // doubles with every attempt: backoff, 2 * backoff, 4 * backoff, ...
+                let exponent = job.attempts.saturating_sub(1).min(16);
+                let delay = self.config.retry_backoff_secs.saturating_mul(1 << exponent).min(MAX_BACKOFF_SECS);
+                doc! {
+                    "$set": { "status": "queued", "run_at": seconds_from_now(delay), "last_error": error.message(), "updated_at": now },
+                    "$unset": { "locked_until": "" },
+                }
+            },
+            false => doc! {
+                "$set": { "status": "failed", "last_error": error.message(), "finished_at": now, "updated_at": now },
+                "$unset": { "locked_until": "", "unique_key": "" },
+            },
+        };
+        self.collection().update_one(Self::claimed(job), update, None).await?;
+
+        Ok(retry)
+    }
+
+    /// Runs one claimed job to completion, recording its outcome
+    async fn execute(&self, job: Job, runner: &dyn JobRunner) -> Result<(), GuavaError> {
+        // a job that keeps stopping its worker, e.g. by crashing the server, is not run again
+        let result = match job.attempts > job.max_attempts {
+            true => Err(GuavaError::Internal(String::from("job was abandoned by its worker too many times"))),
+            false => {
+                let run = runner.run(&job);
+                let heartbeat = self.heartbeat(&job);
+                futures::pin_mut!(run, heartbeat);
+                match futures::future::select(run, heartbeat).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right(_) => unreachable!("heartbeats never finish"),
+                }
+            },
+        };
+        let error = match result {
+            Ok(()) => {
+                tide::log::debug!("Job {} ({:?}) succeeded", job.job_id, job.kind);
+                return self.succeed(&job).await;
+            },
+            Err(e) => e,
+        };
+
+        if self.fail(&job, &error).await? {
+            tide::log::warn!("Job {} ({:?}) failed, retrying: {}", job.job_id, job.kind, error);
+        } else {
+            tide::log::error!("Job {} ({:?}) failed: {}", job.job_id, job.kind, error);
+            runner.give_up(&job, &error).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the configured number of workers running queued jobs
+    pub fn start(&self, runner: Arc<dyn JobRunner>) {
+        for _ in 0..self.config.workers {
+            let service = self.clone();
+            let runner = runner.clone();
+            task::spawn(async move {
+                loop {
+                    match service.claim().await {
+                        Ok(Some(job)) => {
+                            if let Err(e) = service.execute(job, runner.as_ref()).await {
+                                tide::log::error!("Failed to record job outcome: {}", e);
+                            }
+                        },
+                        Ok(None) => {
+                            future::timeout(POLL_INTERVAL, service.wake_receiver.recv()).await.ok();
+                        },
+                        Err(e) => {
+                            tide::log::error!("Failed to claim job: {}", e);
+                            task::sleep(POLL_INTERVAL).await;
+                        },
+                    }
+                }
+            });
+        }
+    }
+}
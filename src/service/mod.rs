@@ -1 +1,49 @@
-pub mod content_service;
\ No newline at end of file
+pub mod auth_service;
+pub mod content_service;
+pub mod ingest_service;
+pub mod job_service;
+pub mod playlist_service;
+pub mod scrub_service;
+pub mod thumbnail_service;
+pub mod user_service;
+
+use futures::stream::TryStreamExt;
+use mongodb::{Collection, bson::Document, error::{Error, ErrorKind, WriteFailure}, options::FindOptions};
+use serde::de::DeserializeOwned;
+use crate::error::GuavaError;
+
+/// Page size of listings when none is requested
+pub const DEFAULT_PAGE_LIMIT: i64 = 50;
+/// Largest page size a listing can request
+pub const MAX_PAGE_LIMIT: i64 = 200;
+
+/// Whether a write failed because it would break a unique index
+pub fn is_duplicate_key(error: &Error) -> bool {
+    matches!(error.kind.as_ref(), ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == 11000)
+}
+
+/// Finds one page of a listing of up to `limit` documents, returning them with the cursor of
+/// the next page, taken from the last document by `cursor`, or `None` on the last page
+pub async fn find_page<T>(
+    collection: &Collection<T>,
+    filter: impl Into<Option<Document>>,
+    mut options: FindOptions,
+    limit: Option<i64>,
+    cursor: impl FnOnce(&T) -> Option<String>,
+) -> Result<(Vec<T>, Option<String>), GuavaError>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+    // one extra to tell whether there is another page
+    options.limit = Some(limit + 1);
+
+    let mut page: Vec<T> = collection.find(filter, options).await?.try_collect().await?;
+    if page.len() as i64 <= limit {
+        return Ok((page, None));
+    }
+
+    page.truncate(limit as usize);
+    let next = page.last().and_then(cursor);
+    Ok((page, next))
+}
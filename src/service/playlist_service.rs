@@ -0,0 +1,728 @@
+use std::collections::{HashMap, HashSet};
+use futures::stream::TryStreamExt;
+use mongodb::{Collection, Database, bson::{self, DateTime, Document, doc, oid::ObjectId}, options::{FindOptions, UpdateModifications}};
+use serde::{Serialize, Deserialize};
+use crate::error::GuavaError;
+use crate::media::MediaInfo;
+use crate::service::{find_page, is_duplicate_key};
+use crate::service::auth_service::{Principal, Role, generate_secret, hash_secret};
+use crate::service::content_service::{Content, ContentService, GuavaContentType};
+
+#[derive(Clone)]
+pub struct PlaylistService {
+    db: Database,
+    content_service: ContentService,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlaylistContent {
+    pub name: String,
+    pub content_type: GuavaContentType,
+    pub content_id: String,
+}
+
+/// Prefix of every share token
+const SHARE_PREFIX: &str = "share_";
+
+/// Who can see a playlist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Listed and readable by anyone
+    #[default]
+    Public,
+    /// Readable by anyone knowing its identifier, but not listed
+    Unlisted,
+    /// Readable only by its owner, admins and holders of a share token
+    Private,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GuavaPlaylist {
+    pub name: String,
+    pub identifier: String,
+    pub content: Option<Vec<PlaylistContent>>,
+    /// Id of the user or API key that created the playlist
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime>,
+}
+
+/// Fields of a playlist deciding who can read it
+#[derive(Clone, Debug, Deserialize)]
+pub struct PlaylistAccess {
+    pub identifier: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Access fields of a playlist along with the ids of its content
+#[derive(Deserialize)]
+struct PlaylistContentAccess {
+    #[serde(flatten)]
+    access: PlaylistAccess,
+    #[serde(default)]
+    content: Option<Vec<PlaylistContentId>>,
+}
+
+#[derive(Deserialize)]
+struct PlaylistContentId {
+    content_id: String,
+}
+
+impl GuavaPlaylist {
+    pub fn access(&self) -> PlaylistAccess {
+        PlaylistAccess {
+            identifier: self.identifier.clone(),
+            owner: self.owner.clone(),
+            visibility: self.visibility,
+        }
+    }
+}
+
+/// Playlist without its content, as returned when listing
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlaylistSummary {
+    #[serde(rename = "_id", skip_serializing)]
+    id: ObjectId,
+    pub name: String,
+    pub identifier: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub visibility: Visibility,
+    #[serde(default)]
+    pub created_at: Option<DateTime>,
+    #[serde(default)]
+    pub updated_at: Option<DateTime>,
+}
+
+/// Field playlists are listed by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaylistSort {
+    Name,
+    #[default]
+    Created,
+    Updated,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filters, sorting and page of a playlist listing
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PlaylistListQuery {
+    /// Only playlists of this owner
+    pub owner: Option<String>,
+    /// Only playlists whose name starts with this
+    pub prefix: Option<String>,
+    pub sort: PlaylistSort,
+    pub order: SortOrder,
+    /// Maximum playlists to return, at most `MAX_PAGE_LIMIT`
+    pub limit: Option<i64>,
+    /// Cursor returned as `next` by the previous page
+    pub after: Option<String>,
+}
+
+/// One page of a playlist listing; `next` is absent on the last page
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistPage {
+    pub playlists: Vec<PlaylistSummary>,
+    pub next: Option<String>,
+}
+
+/// Position after the last playlist of a page, sent to clients hex encoded
+#[derive(Serialize, Deserialize)]
+struct PageCursor {
+    id: ObjectId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    updated: Option<i64>,
+}
+
+impl PageCursor {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).unwrap())
+    }
+
+    fn decode(cursor: &str) -> Result<PageCursor, GuavaError> {
+        hex::decode(cursor).ok()
+            .and_then(|json| serde_json::from_slice(&json).ok())
+            .ok_or_else(|| GuavaError::BadRequest(String::from("invalid cursor")))
+    }
+}
+
+/// Escapes a string for literal use in a regular expression
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Revocable token granting read access to a private playlist
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareToken {
+    pub token_id: String,
+    pub identifier: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub token_hash: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+    pub revoked: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub content_id: String,
+    pub content_type: GuavaContentType,
+    pub hash: String,
+    pub size: u64,
+    pub mime_type: String,
+    #[serde(flatten)]
+    pub media: MediaInfo,
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MissingManifestEntry {
+    pub name: String,
+    pub content_id: String,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlaylistManifest {
+    pub name: String,
+    pub identifier: String,
+    pub content: Vec<ManifestEntry>,
+    pub missing: Vec<MissingManifestEntry>,
+}
+
+/// Longest playlist identifier
+const MAX_IDENTIFIER_LEN: usize = 128;
+
+/// Checks an identifier can be used in a `/playlists/:identifier` path
+fn validate_identifier(identifier: &str) -> Result<(), GuavaError> {
+    if identifier.is_empty() || identifier.len() > MAX_IDENTIFIER_LEN {
+        return Err(GuavaError::Validation(format!("identifiers must be 1 to {} bytes", MAX_IDENTIFIER_LEN)));
+    }
+    if identifier.chars().any(|c| c == '/' || c.is_control()) {
+        return Err(GuavaError::Validation(String::from("identifiers can't contain '/' or control characters")));
+    }
+
+    Ok(())
+}
+
+impl PlaylistService {
+    pub fn new(db: Database, content_service: ContentService) -> Self {
+        PlaylistService {
+            db,
+            content_service,
+        }
+    }
+
+    fn collection(&self) -> Collection<GuavaPlaylist> {
+        self.db.collection::<GuavaPlaylist>("playlist")
+    }
+
+    fn not_found() -> GuavaError {
+        GuavaError::NotFound(String::from("playlist not found"))
+    }
+
+    fn share_tokens(&self) -> Collection<ShareToken> {
+        self.db.collection::<ShareToken>("share_tokens")
+    }
+
+    /// Sets timestamps on playlists created before they were recorded, using their creation time for both
+    pub async fn backfill_timestamps(&self) -> Result<u64, GuavaError> {
+        let pipeline = vec![doc! { "$set": {
+            "created_at": { "$ifNull": ["$created_at", { "$toDate": "$_id" }] },
+            "updated_at": { "$ifNull": ["$created_at", { "$toDate": "$_id" }] },
+        } }];
+        let result = self.collection().update_many(doc! { "updated_at": { "$exists": false } }, UpdateModifications::Pipeline(pipeline), None).await?;
+
+        Ok(result.modified_count)
+    }
+
+    /// Lists a page of playlists visible to `viewer`: public ones plus their own, or all of them for admins
+    pub async fn list(&self, query: PlaylistListQuery, viewer: Option<&Principal>) -> Result<PlaylistPage, GuavaError> {
+        let mut filters: Vec<Document> = vec![];
+        if let Some(owner) = &query.owner {
+            filters.push(doc! { "owner": owner });
+        }
+        if let Some(prefix) = &query.prefix {
+            filters.push(doc! { "name": { "$regex": format!("^{}", escape_regex(prefix)) } });
+        }
+        if viewer.is_none_or(|viewer| viewer.role != Role::Admin) {
+            let mut visible = vec![doc! { "visibility": "public" }, doc! { "visibility": { "$exists": false } }];
+            if let Some(viewer) = viewer {
+                visible.push(doc! { "owner": &viewer.id });
+            }
+            filters.push(doc! { "$or": visible });
+        }
+
+        let (direction, after_op) = match query.order {
+            SortOrder::Asc => (1, "$gt"),
+            SortOrder::Desc => (-1, "$lt"),
+        };
+        let sort = match query.sort {
+            PlaylistSort::Name => doc! { "name": direction, "_id": direction },
+            // ids grow with creation time
+            PlaylistSort::Created => doc! { "_id": direction },
+            PlaylistSort::Updated => doc! { "updated_at": direction, "_id": direction },
+        };
+
+        if let Some(after) = &query.after {
+            let cursor = PageCursor::decode(after)?;
+            let after_filter = match (query.sort, cursor.name, cursor.updated) {
+                (PlaylistSort::Name, Some(name), _) => doc! { "$or": [
+                    { "name": { after_op: &name } },
+                    { "name": &name, "_id": { after_op: cursor.id } },
+                ] },
+                (PlaylistSort::Created, _, _) => doc! { "_id": { after_op: cursor.id } },
+                (PlaylistSort::Updated, _, Some(updated)) => {
+                    let updated = DateTime::from_millis(updated);
+                    doc! { "$or": [
+                        { "updated_at": { after_op: updated } },
+                        { "updated_at": updated, "_id": { after_op: cursor.id } },
+                    ] }
+                },
+                _ => return Err(GuavaError::BadRequest(String::from("cursor does not match sort"))),
+            };
+            filters.push(after_filter);
+        }
+
+        let options = FindOptions::builder()
+            .sort(sort)
+            .projection(doc! { "content": 0 })
+            .build();
+
+        let filter = match filters.is_empty() {
+            true => None,
+            false => Some(doc! { "$and": filters }),
+        };
+        let summaries = self.collection().clone_with_type::<PlaylistSummary>();
+        let (playlists, next) = find_page(&summaries, filter, options, query.limit, |last| Some(PageCursor {
+            id: last.id,
+            name: (query.sort == PlaylistSort::Name).then(|| last.name.clone()),
+            updated: (query.sort == PlaylistSort::Updated).then(|| last.updated_at.map_or(0, |updated| updated.timestamp_millis())),
+        }.encode())).await?;
+
+        Ok(PlaylistPage { playlists, next })
+    }
+
+    pub async fn get(&self, identifier: &str) -> Result<GuavaPlaylist, GuavaError> {
+        match self.collection().find_one(doc! { "identifier": identifier }, None).await? {
+            Some(playlist) => Ok(playlist),
+            None => Err(Self::not_found()),
+        }
+    }
+
+    /// Creates an empty playlist, generating an identifier if none is given
+    pub async fn create(&self, name: String, identifier: Option<String>, visibility: Visibility, owner: String) -> Result<GuavaPlaylist, GuavaError> {
+        let identifier = identifier.unwrap_or_else(|| ObjectId::new().to_hex());
+        validate_identifier(&identifier)?;
+
+        if self.collection().find_one(doc! { "identifier": &identifier }, None).await?.is_some() {
+            return Err(GuavaError::Conflict(String::from("playlist already exists")));
+        }
+
+        let playlist = GuavaPlaylist {
+            name,
+            identifier,
+            content: Some(vec![]),
+            owner: Some(owner),
+            visibility,
+            created_at: Some(DateTime::now()),
+            updated_at: Some(DateTime::now()),
+        };
+
+        match self.collection().insert_one(&playlist, None).await {
+            Ok(_) => Ok(playlist),
+            // created at the same time by another request
+            Err(e) if is_duplicate_key(&e) => Err(GuavaError::Conflict(String::from("playlist already exists"))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Renames a playlist and/or changes its visibility
+    pub async fn update(&self, identifier: &str, name: Option<String>, visibility: Option<Visibility>) -> Result<(), GuavaError> {
+        let mut changes = Document::new();
+        if let Some(name) = name {
+            changes.insert("name", name);
+        }
+        if let Some(visibility) = visibility {
+            changes.insert("visibility", bson::to_bson(&visibility)?);
+        }
+        if changes.is_empty() {
+            return Err(GuavaError::BadRequest(String::from("nothing to update")));
+        }
+        changes.insert("updated_at", DateTime::now());
+
+        let result = self.collection().update_one(doc! { "identifier": identifier }, doc! { "$set": changes }, None).await?;
+
+        match result.matched_count {
+            0 => Err(Self::not_found()),
+            _ => Ok(()),
+        }
+    }
+
+    pub async fn delete(&self, identifier: &str) -> Result<(), GuavaError> {
+        let result = self.collection().delete_one(doc! { "identifier": identifier }, None).await?;
+        if result.deleted_count == 0 {
+            return Err(Self::not_found());
+        }
+
+        self.share_tokens().delete_many(doc! { "identifier": identifier }, None).await?;
+        Ok(())
+    }
+
+    /// Appends content to a playlist, taking its type from the content collection.
+    ///
+    /// `editor` must be able to read the content, so private content can't be published
+    /// by adding it to a public playlist.
+    pub async fn add_content(&self, identifier: &str, name: String, content_id: String, editor: &Principal) -> Result<PlaylistContent, GuavaError> {
+        let playlist = self.get(identifier).await?;
+        if playlist.content.unwrap_or_default().iter().any(|entry| entry.content_id == content_id) {
+            return Err(GuavaError::Conflict(String::from("content already in playlist")));
+        }
+
+        let content = self.content_service.get_content_from_id(content_id).await?;
+        self.authorize_content_read(&content, Some(editor), None).await?;
+        let entry = PlaylistContent {
+            name,
+            content_type: content.content_type,
+            content_id: content.content_id,
+        };
+        let entry_doc = bson::to_bson(&entry)?;
+
+        let result = self.collection().update_one(doc! { "identifier": identifier }, doc! { "$push": { "content": entry_doc }, "$set": { "updated_at": DateTime::now() } }, None).await?;
+        match result.matched_count {
+            0 => Err(Self::not_found()),
+            _ => Ok(entry),
+        }
+    }
+
+    pub async fn remove_content(&self, identifier: &str, content_id: &str) -> Result<(), GuavaError> {
+        let filter = doc! { "identifier": identifier, "content.content_id": content_id };
+
+        let result = self.collection().update_one(filter, doc! { "$pull": { "content": { "content_id": content_id } }, "$set": { "updated_at": DateTime::now() } }, None).await?;
+        if result.matched_count == 0 {
+            // distinguish a missing playlist from a missing entry
+            self.get(identifier).await?;
+            return Err(GuavaError::NotFound(String::from("content not in playlist")));
+        }
+
+        Ok(())
+    }
+
+    /// Reorders playlist content; `order` must list every content id in the playlist exactly once
+    pub async fn reorder_content(&self, identifier: &str, order: Vec<String>) -> Result<Vec<PlaylistContent>, GuavaError> {
+        let invalid_order = || GuavaError::Validation(String::from("order must list every content id exactly once"));
+
+        let mut entries = self.get(identifier).await?.content.unwrap_or_default();
+        if order.len() != entries.len() {
+            return Err(invalid_order());
+        }
+
+        let mut reordered = Vec::with_capacity(entries.len());
+        for content_id in order.iter() {
+            match entries.iter().position(|entry| &entry.content_id == content_id) {
+                Some(index) => reordered.push(entries.swap_remove(index)),
+                None => return Err(invalid_order()),
+            }
+        }
+
+        let content_doc = bson::to_bson(&reordered)?;
+        let result = self.collection().update_one(doc! { "identifier": identifier }, doc! { "$set": { "content": content_doc, "updated_at": DateTime::now() } }, None).await?;
+        match result.matched_count {
+            0 => Err(Self::not_found()),
+            _ => Ok(reordered),
+        }
+    }
+
+    /// Builds the client manifest for a playlist; content that cannot be resolved or isn't ready is listed under `missing`
+    pub async fn manifest(&self, identifier: &str) -> Result<PlaylistManifest, GuavaError> {
+        let playlist = self.get(identifier).await?;
+        let entries = playlist.content.unwrap_or_default();
+
+        let ids = entries.iter().map(|entry| entry.content_id.clone()).collect();
+        let contents: HashMap<String, Content> = self.content_service.get_contents_from_ids(ids).await?
+            .into_iter()
+            .map(|content| (content.content_id.clone(), content))
+            .collect();
+
+        let mut manifest = PlaylistManifest {
+            name: playlist.name,
+            identifier: playlist.identifier,
+            content: Vec::with_capacity(entries.len()),
+            missing: vec![],
+        };
+
+        for entry in entries {
+            let content = match contents.get(&entry.content_id) {
+                Some(content) => content,
+                None => {
+                    manifest.missing.push(MissingManifestEntry {
+                        name: entry.name,
+                        content_id: entry.content_id,
+                        reason: String::from("content not found"),
+                    });
+                    continue;
+                }
+            };
+
+            if let Err(e) = content.ensure_ready() {
+                manifest.missing.push(MissingManifestEntry {
+                    name: entry.name,
+                    content_id: entry.content_id,
+                    reason: e.message(),
+                });
+                continue;
+            }
+
+            let hash = content.hash.clone();
+            // only content uploaded before sizes were recorded needs a round trip to the store
+            let size = match content.size {
+                Some(size) => Ok(size),
+                None => self.content_service.store().size(&hash).await,
+            };
+            match size {
+                Ok(size) => manifest.content.push(ManifestEntry {
+                    name: entry.name,
+                    content_id: entry.content_id,
+                    content_type: content.content_type,
+                    path: format!("rbxasset://custom-content/{}", hash),
+                    hash,
+                    size,
+                    mime_type: content.mime_type.clone().unwrap_or_else(|| content.content_type.mime_type().to_string()),
+                    media: content.media.clone(),
+                }),
+                Err(GuavaError::NotFound(_)) => manifest.missing.push(MissingManifestEntry {
+                    name: entry.name,
+                    content_id: entry.content_id,
+                    reason: String::from("file not found"),
+                }),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Creates the indexes keeping identifiers unique and finding the playlists containing content
+    pub async fn ensure_indexes(&self) -> Result<(), GuavaError> {
+        self.db.run_command(doc! {
+            "createIndexes": "playlist",
+            "indexes": [
+                { "key": { "identifier": 1 }, "name": "playlists_identifier", "unique": true },
+                { "key": { "content.content_id": 1 }, "name": "playlists_content" },
+            ],
+        }, None).await?;
+
+        Ok(())
+    }
+
+    /// Checks whether a playlist may be read by `viewer` or the holder of `share_token`
+    pub async fn can_read(&self, playlist: &PlaylistAccess, viewer: Option<&Principal>, share_token: Option<&str>) -> Result<bool, GuavaError> {
+        if playlist.visibility != Visibility::Private {
+            return Ok(true);
+        }
+        if viewer.is_some_and(|viewer| viewer.can_modify(playlist.owner.as_deref())) {
+            return Ok(true);
+        }
+
+        match share_token {
+            Some(token) => self.share_token_valid(&playlist.identifier, token).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Gets a playlist, failing unless it may be read by `viewer` or the holder of `share_token`
+    pub async fn get_readable(&self, identifier: &str, viewer: Option<&Principal>, share_token: Option<&str>) -> Result<GuavaPlaylist, GuavaError> {
+        let playlist = self.get(identifier).await?;
+
+        match self.can_read(&playlist.access(), viewer, share_token).await? {
+            true => Ok(playlist),
+            // don't reveal that a private playlist exists
+            false => Err(Self::not_found()),
+        }
+    }
+
+    /// Ids of content `viewer` can't list: content only in private playlists they can't edit.
+    ///
+    /// Content the viewer owns is readable regardless, which is left to the caller to allow.
+    pub async fn hidden_content_ids(&self, viewer: &Principal) -> Result<Vec<String>, GuavaError> {
+        if viewer.role == Role::Admin {
+            return Ok(vec![]);
+        }
+
+        let readable: HashSet<String> = self.collection().distinct("content.content_id", doc! {
+            "$or": [{ "visibility": { "$ne": "private" } }, { "owner": &viewer.id }],
+        }, None).await?
+            .into_iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .collect();
+        let private = self.collection().distinct("content.content_id", doc! {
+            "visibility": "private",
+            "owner": { "$ne": &viewer.id },
+        }, None).await?;
+
+        Ok(private.into_iter()
+            .filter_map(|id| id.as_str().map(String::from))
+            .filter(|id| !readable.contains(id))
+            .collect())
+    }
+
+    /// Which of the given content ids `viewer` or the holder of `share_token` can't read,
+    /// checked like `authorize_content_read` over a single query of the playlists holding them.
+    ///
+    /// Content the viewer owns is readable regardless, which is left to the caller to allow.
+    pub async fn unreadable_content_ids(&self, ids: &[String], viewer: Option<&Principal>, share_token: Option<&str>) -> Result<HashSet<String>, GuavaError> {
+        if ids.is_empty() || viewer.is_some_and(|viewer| viewer.role == Role::Admin) {
+            return Ok(HashSet::new());
+        }
+
+        let options = FindOptions::builder()
+            .projection(doc! { "identifier": 1, "owner": 1, "visibility": 1, "content.content_id": 1 })
+            .build();
+        let cursor = self.collection().clone_with_type::<PlaylistContentAccess>()
+            .find(doc! { "content.content_id": { "$in": ids } }, options).await?;
+        let playlists: Vec<PlaylistContentAccess> = cursor.try_collect().await?;
+
+        let (mut listed, mut readable) = (HashSet::new(), HashSet::new());
+        for playlist in playlists {
+            let content_ids = playlist.content.unwrap_or_default().into_iter().map(|content| content.content_id);
+            match self.can_read(&playlist.access, viewer, share_token).await? {
+                true => readable.extend(content_ids),
+                false => listed.extend(content_ids),
+            }
+        }
+
+        Ok(ids.iter()
+            .filter(|id| listed.contains(*id) && !readable.contains(*id))
+            .cloned()
+            .collect())
+    }
+
+    /// Checks whether content may be downloaded by `viewer` or the holder of `share_token`.
+    ///
+    /// Content is only restricted when every playlist containing it is private; it is then
+    /// readable by whoever could read one of those playlists, and by its own owner. Returns
+    /// whether the content is restricted.
+    pub async fn authorize_content_read(&self, content: &Content, viewer: Option<&Principal>, share_token: Option<&str>) -> Result<bool, GuavaError> {
+        let options = FindOptions::builder()
+            .projection(doc! { "identifier": 1, "owner": 1, "visibility": 1 })
+            .build();
+        let cursor = self.collection().clone_with_type::<PlaylistAccess>()
+            .find(doc! { "content.content_id": &content.content_id }, options).await?;
+        let playlists: Vec<PlaylistAccess> = cursor.try_collect().await?;
+
+        if playlists.is_empty() || playlists.iter().any(|playlist| playlist.visibility != Visibility::Private) {
+            return Ok(false);
+        }
+        if viewer.is_some_and(|viewer| viewer.can_modify(content.owner.as_deref())) {
+            return Ok(true);
+        }
+        for playlist in playlists.iter() {
+            if self.can_read(playlist, viewer, share_token).await? {
+                return Ok(true);
+            }
+        }
+
+        match viewer.is_some() || share_token.is_some() {
+            true => Err(GuavaError::Forbidden(String::from("content is private"))),
+            false => Err(GuavaError::Unauthorized(String::from("content is private"))),
+        }
+    }
+
+    /// Creates a share token for a playlist, returning its record and the token itself
+    pub async fn create_share_token(&self, identifier: &str, created_by: String) -> Result<(ShareToken, String), GuavaError> {
+        self.get(identifier).await?;
+
+        let token = generate_secret(SHARE_PREFIX);
+        let share_token = ShareToken {
+            token_id: ObjectId::new().to_hex(),
+            identifier: identifier.to_string(),
+            token_hash: hash_secret(&token),
+            created_by,
+            created_at: DateTime::now(),
+            revoked: false,
+        };
+        self.share_tokens().insert_one(&share_token, None).await?;
+
+        Ok((share_token, token))
+    }
+
+    /// Lists a playlist's share tokens, without their hashes
+    pub async fn list_share_tokens(&self, identifier: &str) -> Result<Vec<ShareToken>, GuavaError> {
+        let cursor = self.share_tokens().find(doc! { "identifier": identifier }, None).await?;
+        let mut tokens: Vec<ShareToken> = cursor.try_collect().await?;
+        for token in tokens.iter_mut() {
+            token.token_hash.clear();
+        }
+
+        Ok(tokens)
+    }
+
+    pub async fn revoke_share_token(&self, identifier: &str, token_id: &str) -> Result<(), GuavaError> {
+        let filter = doc! { "identifier": identifier, "token_id": token_id };
+        let result = self.share_tokens().update_one(filter, doc! { "$set": { "revoked": true } }, None).await?;
+
+        match result.matched_count {
+            0 => Err(GuavaError::NotFound(String::from("share token not found"))),
+            _ => Ok(()),
+        }
+    }
+
+    async fn share_token_valid(&self, identifier: &str, token: &str) -> Result<bool, GuavaError> {
+        if !token.starts_with(SHARE_PREFIX) {
+            return Ok(false);
+        }
+
+        let filter = doc! { "identifier": identifier, "token_hash": hash_secret(token), "revoked": false };
+        Ok(self.share_tokens().find_one(filter, None).await?.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_identifier;
+
+    #[test]
+    fn accepts_identifiers_usable_in_paths() {
+        for identifier in ["lobby", "5f0c8a0e9b1e8a3b4c2d1e0f", "my playlist.v2", "café"] {
+            assert!(validate_identifier(identifier).is_ok(), "{:?}", identifier);
+        }
+        assert!(validate_identifier(&"a".repeat(128)).is_ok());
+    }
+
+    #[test]
+    fn rejects_identifiers_no_route_can_reach() {
+        for identifier in ["", "a/b", "/", "tab\there", "new\nline"] {
+            assert!(validate_identifier(identifier).is_err(), "{:?}", identifier);
+        }
+        assert!(validate_identifier(&"a".repeat(129)).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::{BTreeMap, HashSet}, sync::{Arc, atomic::{AtomicBool, Ordering}}};
+use mongodb::{Database, bson::{DateTime, doc}, options::FindOneOptions};
+use serde::{Serialize, Deserialize};
+use crate::error::GuavaError;
+use crate::service::content_service::ContentService;
+
+#[derive(Clone)]
+pub struct ScrubService {
+    db: Database,
+    content_service: ContentService,
+    running: Arc<AtomicBool>,
+}
+
+/// A blob with a problem, and the content referring to it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScrubIssue {
+    pub hash: String,
+    pub content_ids: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScrubReport {
+    pub started_at: DateTime,
+    pub finished_at: DateTime,
+    /// Number of blobs examined
+    pub checked: u64,
+    /// Blobs whose data does not match their hash
+    pub corrupt: Vec<ScrubIssue>,
+    /// Content whose blob is not in the store
+    pub missing: Vec<ScrubIssue>,
+    /// Blobs whose hash cannot be verified
+    pub unverifiable: Vec<ScrubIssue>,
+    /// Stored blobs not referenced by any content
+    pub orphans: Vec<String>,
+}
+
+impl ScrubService {
+    pub fn new(db: Database, content_service: ContentService) -> Self {
+        ScrubService {
+            db,
+            content_service,
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Rehashes every stored blob and records the report; only one scrub runs at a time
+    pub async fn run(&self) -> Result<ScrubReport, GuavaError> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return Err(GuavaError::Conflict(String::from("scrub already running")));
+        }
+
+        let result = self.scrub().await;
+        self.running.store(false, Ordering::SeqCst);
+        result
+    }
+
+    async fn scrub(&self) -> Result<ScrubReport, GuavaError> {
+        let started_at = DateTime::now();
+
+        let mut content_ids_by_hash: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for content in self.content_service.get_all_contents().await? {
+            for hash in content.hashes() {
+                content_ids_by_hash.entry(hash.clone()).or_default().push(content.content_id.clone());
+            }
+        }
+
+        let store = self.content_service.store();
+        let stored: HashSet<String> = store.list().await?.into_iter().collect();
+
+        let mut report = ScrubReport {
+            started_at,
+            finished_at: started_at,
+            checked: 0,
+            corrupt: vec![],
+            missing: vec![],
+            unverifiable: vec![],
+            orphans: stored.iter()
+                .filter(|hash| !content_ids_by_hash.contains_key(*hash))
+                .cloned()
+                .collect(),
+        };
+        report.orphans.sort();
+
+        for (hash, content_ids) in content_ids_by_hash {
+            if !stored.contains(&hash) {
+                report.missing.push(ScrubIssue { hash, content_ids });
+                continue;
+            }
+
+            match self.content_service.verify_blob(&hash).await {
+                Ok(Some(true)) => {},
+                Ok(Some(false)) => report.corrupt.push(ScrubIssue { hash, content_ids }),
+                Ok(None) => report.unverifiable.push(ScrubIssue { hash, content_ids }),
+                // removed between listing and reading
+                Err(GuavaError::NotFound(_)) => report.missing.push(ScrubIssue { hash, content_ids }),
+                Err(e) => return Err(e),
+            }
+            report.checked += 1;
+        }
+
+        report.finished_at = DateTime::now();
+        self.db.collection::<ScrubReport>("scrub_reports").insert_one(&report, None).await?;
+
+        Ok(report)
+    }
+
+    /// Most recently finished scrub report, if any
+    pub async fn latest_report(&self) -> Result<Option<ScrubReport>, GuavaError> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "finished_at": -1 })
+            .build();
+
+        Ok(self.db.collection::<ScrubReport>("scrub_reports").find_one(None, options).await?)
+    }
+}
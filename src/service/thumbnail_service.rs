@@ -0,0 +1,63 @@
+use async_std::{fs, path::PathBuf, task};
+use mongodb::bson::oid::ObjectId;
+use crate::config::Config;
+use crate::error::GuavaError;
+use crate::media;
+use crate::service::content_service::{ContentService, ContentVariant};
+
+/// Name of the variant previews are stored as
+pub const THUMBNAIL_VARIANT: &str = "thumbnail";
+
+/// Generates previews of content, run as background jobs once content is processed
+#[derive(Clone)]
+pub struct ThumbnailService {
+    content_service: ContentService,
+    staging_dir: PathBuf,
+    max_size: u32,
+}
+
+impl ThumbnailService {
+    pub fn new(content_service: ContentService, config: &Config) -> Self {
+        ThumbnailService {
+            content_service,
+            staging_dir: config.storage.staging_dir.clone().into(),
+            max_size: config.thumbnails.max_size,
+        }
+    }
+
+    /// Renders and stores a preview of the content, if its type has one
+    pub async fn generate(&self, content_id: &str) -> Result<(), GuavaError> {
+        let content = self.content_service.get_content_from_id(content_id.to_string()).await?;
+        let store = self.content_service.store();
+
+        fs::create_dir_all(&self.staging_dir).await?;
+        let source = self.staging_dir.join(format!("preview-{}", ObjectId::new().to_hex()));
+        self.content_service.copy_blob(&content.hash, &source).await?;
+
+        let (blocking_source, content_type, max_size) = (source.clone().into(), content.content_type, self.max_size);
+        let preview = task::spawn_blocking(move || {
+            let source: std::path::PathBuf = blocking_source;
+            media::thumbnail::generate(&source, content_type, max_size)
+        }).await;
+        fs::remove_file(&source).await.ok();
+
+        let preview: PathBuf = match preview? {
+            Some(preview) => preview.into(),
+            None => return Ok(()),
+        };
+
+        let stored = async {
+            let (hash, size) = media::hash_file(&preview).await?;
+            store.put(&hash, &preview).await?;
+            Ok::<_, GuavaError>(ContentVariant {
+                name: String::from(THUMBNAIL_VARIANT),
+                hash,
+                size,
+                mime_type: String::from("image/png"),
+            })
+        }.await;
+        fs::remove_file(&preview).await.ok();
+
+        self.content_service.set_variant(content_id, stored?).await
+    }
+}
@@ -0,0 +1,171 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use mongodb::{Collection, Database, bson::{DateTime, doc, oid::ObjectId}};
+use rand::rngs::OsRng;
+use serde::{Serialize, Deserialize};
+use crate::error::GuavaError;
+use crate::service::is_duplicate_key;
+use crate::service::auth_service::{Role, generate_secret, hash_secret};
+
+/// Prefix of every session token, distinguishing them from API keys
+pub const SESSION_PREFIX: &str = "session_";
+
+#[derive(Clone)]
+pub struct UserService {
+    db: Database,
+    session_ttl_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct User {
+    pub user_id: String,
+    pub username: String,
+    /// Argon2 PHC string
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: DateTime,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Session {
+    /// SHA-256 of the session token
+    pub token_hash: String,
+    pub user_id: String,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+fn validate_credentials(username: &str, password: &str) -> Result<(), GuavaError> {
+    let valid_username = (3..=32).contains(&username.len())
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid_username {
+        return Err(GuavaError::Validation(String::from("usernames must be 3 to 32 letters, digits, '_' or '-'")));
+    }
+    if password.len() < 8 {
+        return Err(GuavaError::Validation(String::from("passwords must be at least 8 characters")));
+    }
+
+    Ok(())
+}
+
+impl UserService {
+    pub fn new(db: Database, session_ttl_secs: u64) -> Self {
+        UserService {
+            db,
+            session_ttl_secs,
+        }
+    }
+
+    fn users(&self) -> Collection<User> {
+        self.db.collection::<User>("users")
+    }
+
+    fn sessions(&self) -> Collection<Session> {
+        self.db.collection::<Session>("sessions")
+    }
+
+    /// Creates the indexes keeping usernames unique, finding sessions by token and removing
+    /// them once expired
+    pub async fn ensure_indexes(&self) -> Result<(), GuavaError> {
+        self.db.run_command(doc! {
+            "createIndexes": "users",
+            "indexes": [
+                { "key": { "username": 1 }, "name": "users_username", "unique": true },
+                { "key": { "user_id": 1 }, "name": "users_id", "unique": true },
+            ],
+        }, None).await?;
+        self.db.run_command(doc! {
+            "createIndexes": "sessions",
+            "indexes": [
+                { "key": { "token_hash": 1 }, "name": "sessions_token" },
+                { "key": { "expires_at": 1 }, "name": "sessions_expiry", "expireAfterSeconds": 0 },
+            ],
+        }, None).await?;
+
+        Ok(())
+    }
+
+    pub async fn register(&self, username: String, password: &str, role: Role) -> Result<User, GuavaError> {
+        validate_credentials(&username, password)?;
+
+        if self.users().find_one(doc! { "username": &username }, None).await?.is_some() {
+            return Err(GuavaError::Conflict(String::from("username already taken")));
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+            .map_err(|e| GuavaError::Internal(format!("failed to hash password: {}", e)))?
+            .to_string();
+
+        let mut user = User {
+            user_id: ObjectId::new().to_hex(),
+            username,
+            password_hash,
+            role,
+            created_at: DateTime::now(),
+        };
+        match self.users().insert_one(&user, None).await {
+            Ok(_) => {},
+            // registered at the same time by another request
+            Err(e) if is_duplicate_key(&e) => return Err(GuavaError::Conflict(String::from("username already taken"))),
+            Err(e) => return Err(e.into()),
+        }
+
+        user.password_hash.clear();
+        Ok(user)
+    }
+
+    pub async fn get(&self, user_id: &str) -> Result<User, GuavaError> {
+        match self.users().find_one(doc! { "user_id": user_id }, None).await? {
+            Some(mut user) => {
+                user.password_hash.clear();
+                Ok(user)
+            },
+            None => Err(GuavaError::NotFound(String::from("user not found"))),
+        }
+    }
+
+    /// Checks a username and password, returning a new session token
+    pub async fn login(&self, username: &str, password: &str) -> Result<(String, Session), GuavaError> {
+        let invalid = || GuavaError::Unauthorized(String::from("invalid username or password"));
+
+        let user = self.users().find_one(doc! { "username": username }, None).await?.ok_or_else(invalid)?;
+        let password_hash = PasswordHash::new(&user.password_hash)
+            .map_err(|e| GuavaError::Internal(format!("stored password hash is invalid: {}", e)))?;
+        if Argon2::default().verify_password(password.as_bytes(), &password_hash).is_err() {
+            return Err(invalid());
+        }
+
+        let token = generate_secret(SESSION_PREFIX);
+
+        let now = DateTime::now();
+        let session = Session {
+            token_hash: hash_secret(&token),
+            user_id: user.user_id,
+            created_at: now,
+            expires_at: DateTime::from_millis(now.timestamp_millis() + self.session_ttl_secs as i64 * 1000),
+        };
+        self.sessions().insert_one(&session, None).await?;
+
+        Ok((token, session))
+    }
+
+    pub async fn logout(&self, token: &str) -> Result<(), GuavaError> {
+        self.sessions().delete_one(doc! { "token_hash": hash_secret(token) }, None).await?;
+        Ok(())
+    }
+
+    /// Looks up the user owning an unexpired session token
+    pub async fn authenticate(&self, token: &str) -> Result<Option<User>, GuavaError> {
+        let filter = doc! { "token_hash": hash_secret(token), "expires_at": { "$gt": DateTime::now() } };
+
+        match self.sessions().find_one(filter, None).await? {
+            Some(session) => match self.get(&session.user_id).await {
+                Ok(user) => Ok(Some(user)),
+                Err(GuavaError::NotFound(_)) => Ok(None),
+                Err(e) => Err(e),
+            },
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::io::ErrorKind;
+use async_std::{fs, io::{BufReader, ReadExt, SeekFrom, prelude::SeekExt}, path::{Path, PathBuf}};
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use crate::error::GuavaError;
+use crate::storage::{BlobReader, BlobStore, blob_not_found, validate_hash};
+
+/// Stores blobs as files in a directory on the local filesystem.
+///
+/// Blobs are sharded by the first two pairs of hash digits, e.g. `ab/cd/abcd...`,
+/// to keep directories small.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+/// Outcome of moving flat files into the sharded layout
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub moved: u64,
+    pub skipped: Vec<String>,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalBlobStore {
+            root: root.into(),
+        }
+    }
+
+    fn shard_dir(&self, hash: &str) -> Result<PathBuf, GuavaError> {
+        validate_hash(hash)?;
+        Ok(self.root.join(&hash[0..2]).join(&hash[2..4]))
+    }
+
+    fn path(&self, hash: &str) -> Result<PathBuf, GuavaError> {
+        Ok(self.shard_dir(hash)?.join(hash))
+    }
+
+    async fn open(&self, hash: &str) -> Result<fs::File, GuavaError> {
+        match fs::File::open(self.path(hash)?).await {
+            Ok(file) => Ok(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(blob_not_found()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Moves blobs stored flat in the root directory into the sharded layout.
+    ///
+    /// Files whose names are not valid hashes are left in place and reported.
+    pub async fn migrate_flat_layout(&self) -> Result<MigrationReport, GuavaError> {
+        let mut report = MigrationReport::default();
+        let mut entries = fs::read_dir(&self.root).await?;
+
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if !entry.file_type().await?.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if validate_hash(&name).is_err() {
+                report.skipped.push(name);
+                continue;
+            }
+
+            let shard_dir = self.shard_dir(&name)?;
+            fs::create_dir_all(&shard_dir).await?;
+            let path = shard_dir.join(&name);
+            if path.exists().await {
+                // already migrated, the flat copy has the same contents
+                fs::remove_file(entry.path()).await?;
+            } else {
+                fs::rename(entry.path(), path).await?;
+            }
+            report.moved += 1;
+        }
+
+        Ok(report)
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, hash: &str, source: &Path) -> Result<(), GuavaError> {
+        let shard_dir = self.shard_dir(hash)?;
+        let path = shard_dir.join(hash);
+        if path.exists().await {
+            fs::remove_file(source).await?;
+            return Ok(());
+        }
+
+        fs::create_dir_all(&shard_dir).await?;
+        if fs::rename(source, &path).await.is_err() {
+            // staging may be on another filesystem; copy via a temporary name so
+            // a partially copied blob is never visible under its hash
+            let temp_path = shard_dir.join(format!(".{}.tmp", hash));
+            fs::copy(source, &temp_path).await?;
+            fs::rename(&temp_path, &path).await?;
+            fs::remove_file(source).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn stream(&self, hash: &str) -> Result<BlobReader, GuavaError> {
+        Ok(Box::new(BufReader::new(self.open(hash).await?)))
+    }
+
+    async fn range(&self, hash: &str, start: u64, len: u64) -> Result<BlobReader, GuavaError> {
+        let mut file = self.open(hash).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+
+        Ok(Box::new(BufReader::new(file.take(len))))
+    }
+
+    async fn exists(&self, hash: &str) -> Result<bool, GuavaError> {
+        Ok(self.path(hash)?.is_file().await)
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), GuavaError> {
+        match fs::remove_file(self.path(hash)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(blob_not_found()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn size(&self, hash: &str) -> Result<u64, GuavaError> {
+        match fs::metadata(self.path(hash)?).await {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(blob_not_found()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<String>, GuavaError> {
+        let mut hashes = vec![];
+        if !self.root.is_dir().await {
+            return Ok(hashes);
+        }
+
+        // walk the two shard levels, ignoring anything that is not a blob
+        let mut dirs = vec![(self.root.clone(), 0)];
+        while let Some((dir, depth)) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next().await {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let file_type = entry.file_type().await?;
+
+                if depth < 2 && file_type.is_dir() {
+                    dirs.push((entry.path(), depth + 1));
+                } else if depth == 2 && file_type.is_file() && validate_hash(&name).is_ok() {
+                    hashes.push(name);
+                }
+            }
+        }
+
+        Ok(hashes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{io::ReadExt, task};
+    use super::*;
+
+    /// Directory removed once the test is done with it
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("guava-local-test-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir.into())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn hash(digit: char) -> String {
+        digit.to_string().repeat(64)
+    }
+
+    async fn read(mut reader: BlobReader) -> Vec<u8> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await.unwrap();
+        bytes
+    }
+
+    #[test]
+    fn put_shards_and_consumes_the_source() {
+        task::block_on(async {
+            let dir = TempDir::new("put");
+            let store = LocalBlobStore::new(dir.0.join("store"));
+            let source = dir.0.join("upload");
+            fs::write(&source, b"hello blob").await.unwrap();
+
+            store.put(&hash('a'), &source).await.unwrap();
+            assert!(!source.exists().await);
+            assert!(dir.0.join("store/aa/aa").join(hash('a')).is_file().await);
+            assert!(store.exists(&hash('a')).await.unwrap());
+            assert_eq!(store.size(&hash('a')).await.unwrap(), 10);
+            assert_eq!(store.get(&hash('a')).await.unwrap(), b"hello blob");
+
+            // storing the same blob again keeps the first copy and still consumes the source
+            fs::write(&source, b"hello blob").await.unwrap();
+            store.put(&hash('a'), &source).await.unwrap();
+            assert!(!source.exists().await);
+            assert_eq!(store.get(&hash('a')).await.unwrap(), b"hello blob");
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn put_copies_across_filesystems() {
+        use std::os::unix::fs::MetadataExt;
+
+        // /dev/shm is usually a tmpfs, so renaming out of it fails
+        let other_fs = std::path::Path::new("/dev/shm");
+        let device = |path: &std::path::Path| std::fs::metadata(path).map(|metadata| metadata.dev()).ok();
+        if device(other_fs).is_none() || device(other_fs) == device(&std::env::temp_dir()) {
+            eprintln!("skipped: no second filesystem to stage on");
+            return;
+        }
+
+        task::block_on(async {
+            let dir = TempDir::new("copy");
+            let store = LocalBlobStore::new(dir.0.join("store"));
+            let source = PathBuf::from(other_fs.join(format!("guava-local-test-{}-upload", std::process::id())));
+            fs::write(&source, b"copied blob").await.unwrap();
+
+            let stored = store.put(&hash('b'), &source).await;
+            fs::remove_file(&source).await.ok();
+            stored.unwrap();
+
+            assert_eq!(store.get(&hash('b')).await.unwrap(), b"copied blob");
+            assert!(!source.exists().await);
+            // the temporary copy was renamed into place
+            let shard_dir = dir.0.join("store/bb/bb");
+            assert_eq!(std::fs::read_dir(&shard_dir).unwrap().count(), 1);
+        });
+    }
+
+    #[test]
+    fn ranges_read_part_of_a_blob() {
+        task::block_on(async {
+            let dir = TempDir::new("range");
+            let store = LocalBlobStore::new(dir.0.join("store"));
+            let source = dir.0.join("upload");
+            fs::write(&source, b"0123456789").await.unwrap();
+            store.put(&hash('c'), &source).await.unwrap();
+
+            assert_eq!(read(store.range(&hash('c'), 2, 5).await.unwrap()).await, b"23456");
+            assert_eq!(read(store.range(&hash('c'), 0, 10).await.unwrap()).await, b"0123456789");
+            assert_eq!(read(store.range(&hash('c'), 8, 100).await.unwrap()).await, b"89");
+            assert_eq!(read(store.range(&hash('c'), 4, 0).await.unwrap()).await, b"");
+        });
+    }
+
+    #[test]
+    fn missing_blobs_are_not_found() {
+        task::block_on(async {
+            let dir = TempDir::new("missing");
+            let store = LocalBlobStore::new(dir.0.join("store"));
+
+            assert!(!store.exists(&hash('d')).await.unwrap());
+            assert!(matches!(store.size(&hash('d')).await, Err(GuavaError::NotFound(_))));
+            assert!(matches!(store.range(&hash('d'), 0, 1).await, Err(GuavaError::NotFound(_))));
+            assert!(matches!(store.delete(&hash('d')).await, Err(GuavaError::NotFound(_))));
+            assert!(store.size("../../etc/passwd").await.is_err());
+        });
+    }
+
+    #[test]
+    fn list_only_returns_sharded_blobs() {
+        task::block_on(async {
+            let dir = TempDir::new("list");
+            let root = dir.0.join("store");
+            let store = LocalBlobStore::new(root.clone());
+            assert!(store.list().await.unwrap().is_empty());
+
+            for digit in ['e', 'f'] {
+                let source = dir.0.join("upload");
+                fs::write(&source, b"blob").await.unwrap();
+                store.put(&hash(digit), &source).await.unwrap();
+            }
+            // flat blobs, temporary copies and other files are not part of the store
+            fs::write(root.join(hash('1')), b"flat").await.unwrap();
+            fs::write(root.join("ee/ee").join(format!(".{}.tmp", hash('e'))), b"partial").await.unwrap();
+            fs::write(root.join("ee/ee/notes.txt"), b"notes").await.unwrap();
+
+            let mut hashes = store.list().await.unwrap();
+            hashes.sort();
+            assert_eq!(hashes, vec![hash('e'), hash('f')]);
+
+            store.delete(&hash('e')).await.unwrap();
+            assert_eq!(store.list().await.unwrap(), vec![hash('f')]);
+        });
+    }
+
+    #[test]
+    fn migration_shards_flat_blobs() {
+        task::block_on(async {
+            let dir = TempDir::new("migrate");
+            let root = dir.0.join("store");
+            let store = LocalBlobStore::new(root.clone());
+            fs::create_dir_all(&root).await.unwrap();
+
+            fs::write(root.join(hash('a')), b"flat a").await.unwrap();
+            fs::write(root.join(hash('b')), b"flat b").await.unwrap();
+            // a blob already in the sharded layout has the same contents as its flat copy
+            fs::create_dir_all(root.join("bb/bb")).await.unwrap();
+            fs::write(root.join("bb/bb").join(hash('b')), b"flat b").await.unwrap();
+            fs::write(root.join("README.txt"), b"not a blob").await.unwrap();
+            fs::write(root.join(hash('A')), b"uppercase").await.unwrap();
+
+            let report = store.migrate_flat_layout().await.unwrap();
+            assert_eq!(report.moved, 2);
+            let mut skipped = report.skipped.clone();
+            skipped.sort();
+            assert_eq!(skipped, vec![hash('A'), String::from("README.txt")]);
+
+            assert_eq!(store.get(&hash('a')).await.unwrap(), b"flat a");
+            assert_eq!(store.get(&hash('b')).await.unwrap(), b"flat b");
+            assert!(!root.join(hash('a')).exists().await);
+            assert!(!root.join(hash('b')).exists().await);
+            assert!(root.join("README.txt").exists().await);
+
+            // running again finds nothing left to move
+            let report = store.migrate_flat_layout().await.unwrap();
+            assert_eq!(report.moved, 0);
+            assert_eq!(report.skipped.len(), 2);
+        });
+    }
+}
@@ -0,0 +1,84 @@
+pub mod local;
+pub mod s3;
+
+use async_std::{io::{BufRead, ReadExt}, path::Path};
+use async_trait::async_trait;
+use crate::error::GuavaError;
+
+/// Reader over (part of) a stored blob
+pub type BlobReader = Box<dyn BufRead + Unpin + Send + Sync + 'static>;
+
+/// Storage for content blobs, addressed by hash
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores the staged file at `source` under `hash`, consuming the file
+    async fn put(&self, hash: &str, source: &Path) -> Result<(), GuavaError>;
+
+    /// Reads a whole blob into memory
+    async fn get(&self, hash: &str) -> Result<Vec<u8>, GuavaError> {
+        let mut data = Vec::new();
+        self.stream(hash).await?.read_to_end(&mut data).await?;
+        Ok(data)
+    }
+
+    /// Streams a whole blob
+    async fn stream(&self, hash: &str) -> Result<BlobReader, GuavaError>;
+
+    /// Streams `len` bytes of a blob starting at `start`
+    async fn range(&self, hash: &str, start: u64, len: u64) -> Result<BlobReader, GuavaError>;
+
+    async fn exists(&self, hash: &str) -> Result<bool, GuavaError>;
+
+    async fn delete(&self, hash: &str) -> Result<(), GuavaError>;
+
+    /// Size of a blob in bytes
+    async fn size(&self, hash: &str) -> Result<u64, GuavaError>;
+
+    /// Hashes of every stored blob
+    async fn list(&self) -> Result<Vec<String>, GuavaError>;
+}
+
+/// Checks a hash is well formed (32 to 128 lowercase hex digits) before it is used
+/// to build a path or key, so a bad hash can never escape the store.
+pub fn validate_hash(hash: &str) -> Result<(), GuavaError> {
+    let valid_length = (32..=128).contains(&hash.len());
+    let valid_digits = hash.bytes().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+
+    if valid_length && valid_digits {
+        Ok(())
+    } else {
+        Err(GuavaError::Internal(format!("invalid content hash '{}'", hash)))
+    }
+}
+
+fn blob_not_found() -> GuavaError {
+    GuavaError::NotFound(String::from("file not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_hash;
+
+    #[test]
+    fn accepts_lowercase_hex_hashes() {
+        assert!(validate_hash(&"0123456789abcdef".repeat(4)).is_ok());
+        assert!(validate_hash(&"a".repeat(32)).is_ok());
+        assert!(validate_hash(&"f".repeat(128)).is_ok());
+    }
+
+    #[test]
+    fn rejects_hashes_that_could_escape_the_store() {
+        let traversal = format!("../{}", "a".repeat(61));
+        for hash in [traversal.as_str(), "../../etc/passwd", "..", "", "/"] {
+            assert!(validate_hash(hash).is_err(), "{:?}", hash);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        assert!(validate_hash(&"A".repeat(64)).is_err());
+        assert!(validate_hash(&"g".repeat(64)).is_err());
+        assert!(validate_hash(&"a".repeat(31)).is_err());
+        assert!(validate_hash(&"a".repeat(129)).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use std::time::Duration;
+use async_std::{fs, io::ReadExt, path::Path};
+use async_trait::async_trait;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle, actions::{DeleteObject, GetObject, HeadObject, ListObjectsV2, PutObject}};
+use surf::{Client, RequestBuilder, Response, StatusCode};
+use crate::error::GuavaError;
+use crate::storage::{BlobReader, BlobStore, blob_not_found, validate_hash};
+
+/// How long presigned request urls stay valid
+const SIGNATURE_DURATION: Duration = Duration::from_secs(300);
+
+/// Stores blobs as objects in an S3-compatible bucket (AWS S3, MinIO, ...)
+pub struct S3BlobStore {
+    client: Client,
+    bucket: Bucket,
+    credentials: Credentials,
+}
+
+impl S3BlobStore {
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key: &str, secret_key: &str) -> Result<Self, GuavaError> {
+        let endpoint = endpoint.parse()
+            .map_err(|_| GuavaError::Internal(format!("invalid S3 endpoint '{}'", endpoint)))?;
+        // path style urls work with MinIO and other self-hosted stores
+        let bucket = Bucket::new(endpoint, UrlStyle::Path, bucket.to_string(), region.to_string())
+            .map_err(|e| GuavaError::Internal(format!("invalid S3 bucket: {}", e)))?;
+
+        Ok(S3BlobStore {
+            client: Client::new(),
+            bucket,
+            credentials: Credentials::new(access_key, secret_key),
+        })
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, GuavaError> {
+        let response = request.await.map_err(|e| GuavaError::Storage(e.to_string()))?;
+
+        match response.status() {
+            StatusCode::NotFound => Err(blob_not_found()),
+            status if status.is_success() => Ok(response),
+            status => Err(GuavaError::Storage(format!("unexpected status {} from S3", status))),
+        }
+    }
+
+    async fn head(&self, hash: &str) -> Result<Response, GuavaError> {
+        validate_hash(hash)?;
+        let url = HeadObject::new(&self.bucket, Some(&self.credentials), hash).sign(SIGNATURE_DURATION);
+        self.send(self.client.head(url)).await
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, hash: &str, source: &Path) -> Result<(), GuavaError> {
+        validate_hash(hash)?;
+        if !self.exists(hash).await? {
+            let url = PutObject::new(&self.bucket, Some(&self.credentials), hash).sign(SIGNATURE_DURATION);
+            let body = surf::Body::from_file(source).await?;
+            self.send(self.client.put(url).body(body)).await?;
+        }
+
+        fs::remove_file(source).await?;
+        Ok(())
+    }
+
+    async fn stream(&self, hash: &str) -> Result<BlobReader, GuavaError> {
+        validate_hash(hash)?;
+        let url = GetObject::new(&self.bucket, Some(&self.credentials), hash).sign(SIGNATURE_DURATION);
+        let mut response = self.send(self.client.get(url)).await?;
+
+        Ok(response.take_body().into_reader())
+    }
+
+    async fn range(&self, hash: &str, start: u64, len: u64) -> Result<BlobReader, GuavaError> {
+        validate_hash(hash)?;
+        if len == 0 {
+            return Ok(Box::new(async_std::io::empty()));
+        }
+
+        let url = GetObject::new(&self.bucket, Some(&self.credentials), hash).sign(SIGNATURE_DURATION);
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        let mut response = self.send(self.client.get(url).header("Range", range)).await?;
+
+        // a store ignoring the range answers 200 with the whole object
+        if response.status() == StatusCode::PartialContent {
+            Ok(response.take_body().into_reader())
+        } else {
+            let mut reader = response.take_body().into_reader();
+            async_std::io::copy(&mut (&mut reader).take(start), &mut async_std::io::sink()).await?;
+            Ok(Box::new(async_std::io::BufReader::new(reader.take(len))))
+        }
+    }
+
+    async fn exists(&self, hash: &str) -> Result<bool, GuavaError> {
+        match self.head(hash).await {
+            Ok(_) => Ok(true),
+            Err(GuavaError::NotFound(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> Result<(), GuavaError> {
+        validate_hash(hash)?;
+        let url = DeleteObject::new(&self.bucket, Some(&self.credentials), hash).sign(SIGNATURE_DURATION);
+        self.send(self.client.delete(url)).await?;
+        Ok(())
+    }
+
+    async fn size(&self, hash: &str) -> Result<u64, GuavaError> {
+        let response = self.head(hash).await?;
+
+        response.header("Content-Length")
+            .and_then(|len| len.as_str().parse().ok())
+            .ok_or_else(|| GuavaError::Storage(String::from("S3 returned no content length")))
+    }
+
+    async fn list(&self) -> Result<Vec<String>, GuavaError> {
+        let mut hashes = vec![];
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut action = ListObjectsV2::new(&self.bucket, Some(&self.credentials));
+            if let Some(token) = continuation_token.as_deref() {
+                action.with_continuation_token(token);
+            }
+            let url = action.sign(SIGNATURE_DURATION);
+
+            let mut response = self.send(self.client.get(url)).await?;
+            let body = response.body_string().await.map_err(|e| GuavaError::Storage(e.to_string()))?;
+            let listing = ListObjectsV2::parse_response(&body).map_err(|e| GuavaError::Storage(e.to_string()))?;
+
+            hashes.extend(listing.contents.into_iter()
+                .map(|object| object.key)
+                .filter(|key| validate_hash(key).is_ok()));
+
+            match listing.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => return Ok(hashes),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{io::ReadExt, task};
+    use super::*;
+
+    /// Store named by `GUAVA_TEST_S3_ENDPOINT`, `GUAVA_TEST_S3_BUCKET`,
+    /// `GUAVA_TEST_S3_ACCESS_KEY` and `GUAVA_TEST_S3_SECRET_KEY`, e.g. a local MinIO
+    fn test_store() -> Option<S3BlobStore> {
+        let var = |name: &str| std::env::var(format!("GUAVA_TEST_S3_{}", name)).ok();
+        let region = var("REGION").unwrap_or_else(|| String::from("us-east-1"));
+
+        Some(S3BlobStore::new(&var("ENDPOINT")?, &var("BUCKET")?, &region, &var("ACCESS_KEY")?, &var("SECRET_KEY")?).unwrap())
+    }
+
+    #[test]
+    #[ignore = "needs an S3-compatible store, see test_store"]
+    fn stores_and_reads_blobs() {
+        let store = match test_store() {
+            Some(store) => store,
+            None => return eprintln!("skipped: GUAVA_TEST_S3_ENDPOINT, _BUCKET, _ACCESS_KEY and _SECRET_KEY are not set"),
+        };
+
+        task::block_on(async {
+            // unlikely to collide with real content in a shared bucket
+            let hash = format!("{:0>64x}", std::process::id());
+            let source = std::env::temp_dir().join(format!("guava-s3-test-{}", std::process::id()));
+            fs::write(&source, b"0123456789").await.unwrap();
+
+            store.put(&hash, source.as_path().into()).await.unwrap();
+            assert!(!source.exists());
+            assert!(store.exists(&hash).await.unwrap());
+            assert_eq!(store.size(&hash).await.unwrap(), 10);
+            assert_eq!(store.get(&hash).await.unwrap(), b"0123456789");
+            assert!(store.list().await.unwrap().contains(&hash));
+
+            let mut part = vec![];
+            store.range(&hash, 2, 5).await.unwrap().read_to_end(&mut part).await.unwrap();
+            assert_eq!(part, b"23456");
+
+            store.delete(&hash).await.unwrap();
+            assert!(!store.exists(&hash).await.unwrap());
+            assert!(matches!(store.size(&hash).await, Err(GuavaError::NotFound(_))));
+        });
+    }
+}